@@ -0,0 +1,153 @@
+//! Schulkalender mit Wochenenden, Ferien und dem Schuljahr.
+//!
+//! Der Kalender wird aus der Ferienliste und dem aktuellen Schuljahr von Untis erstellt und entscheidet, ob an einem Tag
+//! Unterricht stattfindet.
+
+use std::fmt;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use log::warn;
+
+use crate::{
+    master_data::{fetch_holidays, fetch_school_year, Holiday, MasterData, SchoolYear},
+    request::Client,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 'DayKind' gibt an ob an einem Tag Unterricht stattfindet
+pub enum DayKind {
+    /// An dem Tag findet Unterricht statt
+    SchoolDay,
+    /// Der Tag ist ein Samstag oder Sonntag
+    Weekend,
+    /// Der Tag liegt in den Ferien bzw. ist unterrichtsfrei, enthält den Langnamen der Ferien
+    Holiday(String),
+    /// Der Tag liegt außerhalb des aktuellen Schuljahrs, enthält den Namen des Schuljahrs
+    OutsideSchoolYear(String),
+}
+
+impl DayKind {
+    /// Gibt zurück ob an dem Tag Unterricht stattfindet
+    pub fn is_school_day(&self) -> bool {
+        *self == DayKind::SchoolDay
+    }
+}
+
+impl fmt::Display for DayKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DayKind::SchoolDay => write!(f, "Schultag"),
+            DayKind::Weekend => write!(f, "Wochenende"),
+            DayKind::Holiday(name) => write!(f, "Ferien {}", name),
+            DayKind::OutsideSchoolYear(name) => write!(f, "außerhalb des Schuljahrs {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// 'Calendar' enthält die unterrichtsfreien Tage und das aktuelle Schuljahr der Schule
+pub struct Calendar {
+    /// Ferien und unterrichtsfreie Tage
+    holidays: Vec<Holiday>,
+    /// Aktuelles Schuljahr, ohne Schuljahr wird nur nach Wochenenden und Ferien entschieden
+    school_year: Option<SchoolYear>,
+}
+
+impl Calendar {
+    /// Ruft die Ferien und das Schuljahr aus Untis ab. Die Ferien sind erforderlich, das Schuljahr wird bei einem Fehler
+    /// mit einer Warnung ausgelassen.
+    ///
+    /// # Arguments
+    /// * `client` - Untis Client mit dem die Ferien abgerufen werden
+    pub fn fetch(client: &mut Client) -> Result<Self> {
+        let holidays = fetch_holidays(client)?;
+        let school_year = match fetch_school_year(client) {
+            Ok(year) => Some(year),
+            Err(e) => {
+                warn!("Schuljahr konnte nicht abgerufen werden. {:#?}", e);
+                None
+            }
+        };
+        Ok(Self { holidays, school_year })
+    }
+
+    /// Erstellt den Kalender aus bereits abgerufenen Stammdaten
+    pub fn from_master_data(master_data: &MasterData) -> Self {
+        Self { holidays: master_data.holidays.clone(), school_year: master_data.school_year.clone() }
+    }
+
+    /// Gibt zurück ob an dem Tag Unterricht stattfindet
+    ///
+    /// # Arguments
+    /// * `date` - Tag der geprüft werden soll
+    pub fn classify(&self, date: NaiveDate) -> DayKind {
+        if matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            return DayKind::Weekend;
+        }
+        if let Some(year) = self.school_year.as_ref().filter(|year| date < year.start || year.end < date) {
+            return DayKind::OutsideSchoolYear(year.name.clone());
+        }
+        match self.holidays.iter().find(|holiday| holiday.start <= date && date <= holiday.end) {
+            Some(holiday) => DayKind::Holiday(holiday.long_name.clone()),
+            None => DayKind::SchoolDay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gibt ein Datum im Jahr 2023 zurück
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, month, day).unwrap()
+    }
+
+    /// Erstellt einen Kalender mit den Herbstferien vom 23.10. bis 03.11.2023 und dem Schuljahr 2023/2024
+    fn calendar() -> Calendar {
+        Calendar {
+            holidays: vec![Holiday {
+                name: "HF".to_string(),
+                long_name: "Herbstferien".to_string(),
+                start: date(10, 23),
+                end: date(11, 3),
+            }],
+            school_year: Some(SchoolYear {
+                id: 1,
+                name: "2023/2024".to_string(),
+                start: date(8, 7),
+                end: NaiveDate::from_ymd_opt(2024, 7, 26).unwrap(),
+            }),
+        }
+    }
+
+    #[test]
+    fn weekends_are_no_school_days() {
+        assert_eq!(Calendar::default().classify(date(11, 18)), DayKind::Weekend);
+        assert_eq!(Calendar::default().classify(date(11, 19)), DayKind::Weekend);
+        assert!(Calendar::default().classify(date(11, 20)).is_school_day());
+        // Ein Wochenende in den Ferien bleibt ein Wochenende
+        assert_eq!(calendar().classify(date(10, 28)), DayKind::Weekend);
+    }
+
+    #[test]
+    fn holidays_include_first_and_last_day() {
+        let calendar = calendar();
+        assert_eq!(calendar.classify(date(10, 20)), DayKind::SchoolDay);
+        assert_eq!(calendar.classify(date(10, 23)), DayKind::Holiday("Herbstferien".to_string()));
+        assert_eq!(calendar.classify(date(11, 3)), DayKind::Holiday("Herbstferien".to_string()));
+        assert_eq!(calendar.classify(date(11, 6)), DayKind::SchoolDay);
+    }
+
+    #[test]
+    fn days_outside_the_school_year() {
+        let calendar = calendar();
+        assert_eq!(calendar.classify(date(8, 4)), DayKind::OutsideSchoolYear("2023/2024".to_string()));
+        assert_eq!(calendar.classify(date(8, 7)), DayKind::SchoolDay);
+        assert_eq!(calendar.classify(NaiveDate::from_ymd_opt(2024, 7, 26).unwrap()), DayKind::SchoolDay);
+        assert_eq!(calendar.classify(NaiveDate::from_ymd_opt(2024, 7, 29).unwrap()), DayKind::OutsideSchoolYear("2023/2024".to_string()));
+        assert_eq!(calendar.classify(date(8, 4)).to_string(), "außerhalb des Schuljahrs 2023/2024");
+    }
+}
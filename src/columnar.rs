@@ -0,0 +1,294 @@
+//! Export der Tagesdateien als spaltenorientierter Datensatz im Parquet oder Arrow IPC Format.
+//!
+//! Der Datensatz enthält eine Zeile pro Unterrichtsstunde und Snapshot und wird nach dem Monat der Unterrichtsstunde partitioniert:
+//!
+//! ```text
+//! AUSGABE/year=2023/month=10/lessons.parquet
+//! AUSGABE/year=2023/month=11/lessons.parquet
+//! ```
+//!
+//! Die Partitionen können z.B. mit DuckDB über `read_parquet('AUSGABE/**/*.parquet', hive_partitioning = true)` gelesen werden.
+//! Klassen, Lehrer, Räume und Fächer sind Listenspalten, die Art des Snapshots und der Unterrichtsstunde sind dictionary-kodiert.
+//!
+//! Jede Tagesdatei wird als eigener RecordBatch in die offenen Partitionen geschrieben, sodass nie mehr als die Zeilen einer
+//! Tagesdatei im Speicher liegen. Arrow IPC Dateien erlauben nur ein Dictionary je Spalte, daher enthalten die Dictionaries
+//! immer alle Arten, auch wenn sie in einem RecordBatch nicht vorkommen.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow::{
+    array::{
+        ArrayRef, BooleanBuilder, Date32Builder, ListBuilder, StringArray, StringBuilder, StringDictionaryBuilder, Time32SecondBuilder,
+        TimestampMicrosecondBuilder, UInt64Builder,
+    },
+    datatypes::{Int8Type, SchemaRef},
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use chrono::{Datelike, Timelike};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+
+use crate::data::{ExportFile, Lesson, LessonCode, Snapshot, SnapshotKind, Substitution};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Anzahl der Tage zwischen dem 01.01.0001 und dem 01.01.1970, Arrow speichert Daten als Tage seit 1970
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'ColumnarFormat' repräsentiert die spaltenorientierten Formate in die exportiert werden kann
+pub enum ColumnarFormat {
+    /// Apache Parquet
+    Parquet,
+    /// Arrow IPC (Feather v2)
+    Arrow,
+}
+
+impl ColumnarFormat {
+    /// Dateiendung der Partitionen
+    fn extension(&self) -> &'static str {
+        match self {
+            ColumnarFormat::Parquet => "parquet",
+            ColumnarFormat::Arrow => "arrow",
+        }
+    }
+}
+
+/// 'LessonColumns' sammelt die Unterrichtsstunden einer Partition spaltenweise
+struct LessonColumns {
+    snapshot: TimestampMicrosecondBuilder,
+    snapshot_kind: StringDictionaryBuilder<Int8Type>,
+    master_data_version: StringBuilder,
+    pseudonym_key: StringBuilder,
+    snapshot_complete: BooleanBuilder,
+    date: Date32Builder,
+    start_time: Time32SecondBuilder,
+    end_time: Time32SecondBuilder,
+    lesson_id: UInt64Builder,
+    classes: ListBuilder<StringBuilder>,
+    teachers: ListBuilder<StringBuilder>,
+    rooms: ListBuilder<StringBuilder>,
+    lesson_code: StringDictionaryBuilder<Int8Type>,
+    description: StringBuilder,
+    topic: StringBuilder,
+    subjects: ListBuilder<StringBuilder>,
+    subjects_long: ListBuilder<StringBuilder>,
+    sub_text: StringBuilder,
+    teacher_substitutions_original: ListBuilder<StringBuilder>,
+    teacher_substitutions_replacement: ListBuilder<StringBuilder>,
+    room_substitutions_original: ListBuilder<StringBuilder>,
+    room_substitutions_replacement: ListBuilder<StringBuilder>,
+}
+
+impl LessonColumns {
+    /// Erstellt leere Spalten
+    fn new() -> Result<Self> {
+        let snapshot_kinds = StringArray::from_iter_values([SnapshotKind::Live, SnapshotKind::Backfill].map(|kind| format!("{:?}", kind)));
+        let lesson_codes =
+            StringArray::from_iter_values([LessonCode::Regular, LessonCode::Irregular, LessonCode::Cancelled].map(|code| format!("{:?}", code)));
+        Ok(Self {
+            snapshot: TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            snapshot_kind: StringDictionaryBuilder::new_with_dictionary(0, &snapshot_kinds)?,
+            master_data_version: StringBuilder::new(),
+            pseudonym_key: StringBuilder::new(),
+            snapshot_complete: BooleanBuilder::new(),
+            date: Date32Builder::new(),
+            start_time: Time32SecondBuilder::new(),
+            end_time: Time32SecondBuilder::new(),
+            lesson_id: UInt64Builder::new(),
+            classes: ListBuilder::new(StringBuilder::new()),
+            teachers: ListBuilder::new(StringBuilder::new()),
+            rooms: ListBuilder::new(StringBuilder::new()),
+            lesson_code: StringDictionaryBuilder::new_with_dictionary(0, &lesson_codes)?,
+            description: StringBuilder::new(),
+            topic: StringBuilder::new(),
+            subjects: ListBuilder::new(StringBuilder::new()),
+            subjects_long: ListBuilder::new(StringBuilder::new()),
+            sub_text: StringBuilder::new(),
+            teacher_substitutions_original: ListBuilder::new(StringBuilder::new()),
+            teacher_substitutions_replacement: ListBuilder::new(StringBuilder::new()),
+            room_substitutions_original: ListBuilder::new(StringBuilder::new()),
+            room_substitutions_replacement: ListBuilder::new(StringBuilder::new()),
+        })
+    }
+
+    /// Fügt eine Unterrichtsstunde eines Snapshots als Zeile hinzu
+    fn push(&mut self, snapshot: &Snapshot, lesson: &Lesson) -> Result<()> {
+        self.snapshot.append_value(snapshot.datetime().timestamp_micros());
+        self.snapshot_kind.append(format!("{:?}", snapshot.kind()))?;
+        self.master_data_version.append_option(snapshot.master_data_version());
+        self.pseudonym_key.append_option(snapshot.pseudonym_key());
+        self.snapshot_complete.append_option(snapshot.is_complete());
+        self.date.append_value(lesson.date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE);
+        self.start_time.append_value(lesson.start_time.num_seconds_from_midnight() as i32);
+        self.end_time.append_value(lesson.end_time.num_seconds_from_midnight() as i32);
+        self.lesson_id.append_value(lesson.id as u64);
+        append_list(&mut self.classes, &lesson.classes);
+        append_list(&mut self.teachers, &lesson.teachers);
+        append_list(&mut self.rooms, &lesson.rooms);
+        self.lesson_code.append(format!("{:?}", lesson.lesson_code))?;
+        self.description.append_value(&lesson.description);
+        self.topic.append_option(lesson.topic());
+        // Nicht erfasste Fächer werden als leerer Wert statt als leere Liste geschrieben
+        for subject in lesson.subjects.iter().flatten() {
+            self.subjects.values().append_value(&subject.name);
+            self.subjects_long.values().append_option(subject.long_name.as_deref());
+        }
+        self.subjects.append(lesson.subjects.is_some());
+        self.subjects_long.append(lesson.subjects.is_some());
+        self.sub_text.append_option(lesson.sub_text.as_deref());
+        append_substitutions(
+            &mut self.teacher_substitutions_original,
+            &mut self.teacher_substitutions_replacement,
+            &lesson.teacher_substitutions,
+        );
+        append_substitutions(&mut self.room_substitutions_original, &mut self.room_substitutions_replacement, &lesson.room_substitutions);
+        Ok(())
+    }
+
+    /// Erstellt aus den gesammelten Spalten einen RecordBatch
+    fn finish(mut self) -> Result<RecordBatch> {
+        let columns: Vec<(&str, ArrayRef)> = vec![
+            ("snapshot", Arc::new(self.snapshot.finish())),
+            ("snapshot_kind", Arc::new(self.snapshot_kind.finish())),
+            ("master_data_version", Arc::new(self.master_data_version.finish())),
+            ("pseudonym_key", Arc::new(self.pseudonym_key.finish())),
+            ("snapshot_complete", Arc::new(self.snapshot_complete.finish())),
+            ("date", Arc::new(self.date.finish())),
+            ("start_time", Arc::new(self.start_time.finish())),
+            ("end_time", Arc::new(self.end_time.finish())),
+            ("lesson_id", Arc::new(self.lesson_id.finish())),
+            ("classes", Arc::new(self.classes.finish())),
+            ("teachers", Arc::new(self.teachers.finish())),
+            ("rooms", Arc::new(self.rooms.finish())),
+            ("lesson_code", Arc::new(self.lesson_code.finish())),
+            ("description", Arc::new(self.description.finish())),
+            ("topic", Arc::new(self.topic.finish())),
+            ("subjects", Arc::new(self.subjects.finish())),
+            ("subjects_long", Arc::new(self.subjects_long.finish())),
+            ("sub_text", Arc::new(self.sub_text.finish())),
+            ("teacher_substitutions_original", Arc::new(self.teacher_substitutions_original.finish())),
+            ("teacher_substitutions_replacement", Arc::new(self.teacher_substitutions_replacement.finish())),
+            ("room_substitutions_original", Arc::new(self.room_substitutions_original.finish())),
+            ("room_substitutions_replacement", Arc::new(self.room_substitutions_replacement.finish())),
+        ];
+        Ok(RecordBatch::try_from_iter(columns)?)
+    }
+}
+
+/// Fügt eine Liste von Strings als Wert einer Listenspalte hinzu
+fn append_list(builder: &mut ListBuilder<StringBuilder>, values: &[String]) {
+    for value in values {
+        builder.values().append_value(value);
+    }
+    builder.append(true);
+}
+
+/// Fügt Ersetzungen als zwei gleich lange Listenspalten (ursprünglich und ersetzend) hinzu
+fn append_substitutions(original: &mut ListBuilder<StringBuilder>, replacement: &mut ListBuilder<StringBuilder>, values: &[Substitution]) {
+    for substitution in values {
+        original.values().append_value(&substitution.original);
+        replacement.values().append_value(&substitution.replacement);
+    }
+    original.append(true);
+    replacement.append(true);
+}
+
+/// 'PartitionWriter' schreibt die RecordBatches einer Partition nacheinander in ihre Datei
+enum PartitionWriter {
+    /// Schreibt eine Parquet Datei
+    Parquet(ArrowWriter<File>),
+    /// Schreibt eine Arrow IPC Datei
+    Arrow(FileWriter<File>),
+}
+
+impl PartitionWriter {
+    /// Erstellt die Datei einer Partition
+    ///
+    /// # Arguments
+    /// * `path` - Pfad der Datei
+    /// * `schema` - Schema aller RecordBatches der Partition
+    /// * `format` - Format der Partition
+    fn create(path: &Path, schema: SchemaRef, format: ColumnarFormat) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(match format {
+            ColumnarFormat::Parquet => {
+                let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+                PartitionWriter::Parquet(ArrowWriter::try_new(file, schema, Some(properties))?)
+            }
+            ColumnarFormat::Arrow => PartitionWriter::Arrow(FileWriter::try_new(file, &schema)?),
+        })
+    }
+
+    /// Schreibt einen RecordBatch in die Partition
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            PartitionWriter::Parquet(writer) => writer.write(batch)?,
+            PartitionWriter::Arrow(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    /// Schließt die Datei der Partition ab
+    fn close(self) -> Result<()> {
+        match self {
+            PartitionWriter::Parquet(writer) => {
+                writer.close()?;
+            }
+            PartitionWriter::Arrow(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Exportiert Tagesdateien als nach Monaten partitionierten Datensatz
+///
+/// # Arguments
+/// * `files` - Tagesdateien die exportiert werden sollen
+/// * `format` - Format der Partitionen
+/// * `output` - Ordner in dem der Datensatz erstellt wird
+///
+/// # Returns
+/// * `usize` - Anzahl der exportierten Zeilen
+pub fn export_dataset(files: &[PathBuf], format: ColumnarFormat, output: &Path) -> Result<usize> {
+    let mut writers: BTreeMap<(i32, u32), PartitionWriter> = BTreeMap::new();
+    let mut count = 0;
+    for file in files {
+        // Sammelt die Zeilen einer Tagesdatei nach Monat der Unterrichtsstunde
+        let mut partitions: BTreeMap<(i32, u32), LessonColumns> = BTreeMap::new();
+        let export_file = ExportFile::read(file)?;
+        for (snapshot, lesson) in export_file.lessons() {
+            let partition = (lesson.date.year(), lesson.date.month());
+            let columns = match partitions.entry(partition) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(LessonColumns::new()?),
+            };
+            columns.push(snapshot, lesson)?;
+            count += 1;
+        }
+
+        // Hängt die Zeilen an die Partitionen an, jede Partition liegt in einem eigenen Ordner
+        for ((year, month), columns) in partitions {
+            let batch = columns.finish()?;
+            let writer = match writers.entry((year, month)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let folder = output.join(format!("year={}", year)).join(format!("month={:02}", month));
+                    fs::create_dir_all(&folder)?;
+                    entry.insert(PartitionWriter::create(&folder.join(format!("lessons.{}", format.extension())), batch.schema(), format)?)
+                }
+            };
+            writer.write(&batch)?;
+        }
+    }
+
+    for writer in writers.into_values() {
+        writer.close()?;
+    }
+    Ok(count)
+}
@@ -0,0 +1,657 @@
+//! Implementierung der Unterbefehle des Kommandozeilenprogramms.
+//! Die Unterbefehle die Untis abfragen werden über die `Config` gesteuert, die übrigen benötigen nur `STORAGE_PATH`.
+//! Alle lesen bzw. schreiben die Tagesdateien unter `STORAGE_PATH`.
+
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDate, Utc};
+use log::{error, info, warn};
+
+use crate::{
+    calendar::{Calendar, DayKind},
+    config::Config,
+    data::{ExportFile, LessonCode, Snapshot, SnapshotKind},
+    diff,
+    export::{self, ExportFormat},
+    master_data::MasterData,
+    privacy::Anonymizer,
+    pseudonym::{Linkage, Pseudonymizer},
+    reader::ArchiveReader,
+    scraper::{add_school_days, create_backfill_snapshot, create_snapshot},
+    state::{update_state, ReportedState, State},
+    request::Client,
+    storage::{self, ArchiveError, SnapshotReader, FORMAT_VERSION},
+    vault,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Ruft den Stundenplan ab und hängt einen neuen Snapshot an die Tagesdatei an.
+/// Ist ein Hauptserver konfiguriert, wird vorher geprüft ob dieser bereits erfolgreich gelaufen ist.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+pub fn scrape(config: &Config) {
+    // Wochenenden werden ohne Login übersprungen
+    let today = Local::now().date_naive();
+    if skip_non_school_day(config, &Calendar::default(), today) {
+        return;
+    }
+    if primary_has_run(config) {
+        return;
+    }
+
+    // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf STARTED gesetzt
+    set_state(config, State::STARTED);
+
+    // Erstellt einen neuen Client und loggt sich ein. Wenn das Login fehlschlägt wird eine Fehlermeldung ausgegeben und das Programm beendet.
+    let mut client = match login(config) {
+        Ok(client) => client,
+        Err(e) => {
+            finish_run(config, Err(e));
+            return;
+        }
+    };
+
+    // Ferien und Tage außerhalb des Schuljahrs werden nach dem Login übersprungen
+    match Calendar::fetch(&mut client) {
+        Ok(calendar) => {
+            if skip_non_school_day(config, &calendar, today) {
+                return;
+            }
+        }
+        Err(e) => warn!("Ferien konnten nicht abgerufen werden, es wird trotzdem abgerufen. {:#?}", e),
+    }
+
+    finish_run(config, scrape_with_client(&mut client, config));
+}
+
+/// Prüft ob an dem Tag Unterricht stattfindet. Ist das nicht der Fall, wird der Durchlauf übersprungen und der Grund
+/// als Status SKIPPED gesetzt, statt einen leeren Snapshot zu speichern.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `calendar` - Kalender der Schule
+/// * `date` - Tag des Durchlaufs
+///
+/// # Returns
+/// * `true` - Wenn der Durchlauf übersprungen wird
+pub(crate) fn skip_non_school_day(config: &Config, calendar: &Calendar, date: NaiveDate) -> bool {
+    let day = calendar.classify(date);
+    if day.is_school_day() {
+        return false;
+    }
+    info!("Durchlauf übersprungen: {}.", day);
+    set_state(config, State::SKIPPED(day.to_string()));
+    true
+}
+
+/// Prüft über `STATE_CHECK_URL` ob das Programm auf dem Hauptserver gerade läuft oder in der letzten Stunde erfolgreich
+/// ausgeführt wurde. Ist `STATE_CHECK_URL` nicht gesetzt, wird immer `false` zurückgegeben.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+pub(crate) fn primary_has_run(config: &Config) -> bool {
+    // Wenn STATE_CHECK_URL gesetzt ist wird der Status des Programms auf dem Hauptserver abgefragt
+    if let Some(status_file_check) = &config.state_file_check {
+        match reqwest::blocking::get(status_file_check) {
+            // Wenn der Status erfolgreich abgerufen wurde, wird überprüft ob das Programm bereits läuft oder erfolgreich ausgeführt wurde
+            Ok(response) => {
+                if response.status().is_success() {
+                    // Deserialisiert den Status, ist er ungültig wird das Programm fortgesetzt
+                    let state: ReportedState = match response.json() {
+                        Ok(state) => state,
+                        Err(e) => {
+                            error!("Status des Hauptservers ist ungültig: \"{:#?}\"", e);
+                            info!("Daten werden abgerufen.");
+                            return false;
+                        }
+                    };
+                    // Prüfe ob der Status vor weniger als einer Stunde gesetzt wurde
+                    if state.timestamp + chrono::Duration::hours(1) > Utc::now() {
+                        // Wenn der Status vor weniger als einer Stunde gesetzt wurde, wird überprüft ob das Programm bereits läuft oder erfolgreich ausgeführt wurde
+                        match state.state {
+                            // Wenn das Programm bereits läuft wird eine Meldung ausgegeben und das Programm beendet
+                            State::STARTED => {
+                                info!("Das Programm läuft auf den Hauptserver bereits.");
+                                return true;
+                            }
+                            // Wenn das Programm erfolgreich ausgeführt wurde wird eine Meldung ausgegeben und das Programm beendet
+                            State::SUCCESS => {
+                                info!("Das Programm wurde auf den Hauptserver erfolgreich ausgeführt.");
+                                return true;
+                            }
+
+                            // Wenn der Hauptserver den Durchlauf übersprungen hat, findet heute kein Unterricht statt
+                            State::SKIPPED(reason) => {
+                                info!("Der Hauptserver hat den Durchlauf übersprungen: {}.", reason);
+                                return true;
+                            }
+
+                            // Wenn das Programm mit einem Fehler beendet wurde wird eine Meldung ausgegeben und das Programm wird fortgesetzt
+                            State::ERROR(error_msg) => {
+                                error!("Hauptserver hat den Fehler: \"{}\"", error_msg);
+                                info!("Daten werden Lokal abgerufen.")
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Wenn der Status nicht erfolgreich abgerufen werden konnte wird eine Meldung ausgegeben und das Programm wird fortgesetzt
+            Err(e) => {
+                let error_msg = format!("Fehler beim abrufen des Status: \"{:#?}\"", e);
+                error!("{}", error_msg);
+                info!("Daten werden abgerufen.");
+            }
+        }
+    }
+    false
+}
+
+/// Setzt den Status des Programms, wenn `STATE_PATH` gesetzt ist. Fehler beim Setzen werden nur geloggt.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `state` - Status der gesetzt werden soll
+pub(crate) fn set_state(config: &Config, state: State) {
+    if let Some(path) = &config.state_file_path {
+        if let Err(e) = update_state(path, state) {
+            let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
+            error!("{}", error_msg);
+        }
+    }
+}
+
+/// Loggt sich bei Untis ein
+///
+/// # Arguments
+/// * `config` - Konfiguration mit den Zugangsdaten
+pub(crate) fn login(config: &Config) -> Result<Client> {
+    Client::login(config).map_err(|e| anyhow::anyhow!("Login fehlgeschlagen. {:#?}", e))
+}
+
+/// Loggt das Ergebnis eines Durchlaufs und setzt den Status auf SUCCESS bzw. ERROR
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `result` - Ergebnis des Durchlaufs
+pub(crate) fn finish_run(config: &Config, result: Result<()>) {
+    match result {
+        Ok(()) => {
+            set_state(config, State::SUCCESS);
+            info!("Daten wurden erfolgreich abgerufen.");
+        }
+        Err(e) => {
+            let error_msg = e.to_string();
+            error!("{}", error_msg);
+            set_state(config, State::ERROR(error_msg));
+        }
+    }
+}
+
+/// Ruft mit einem eingeloggten Client die Stammdaten und den Stundenplan ab und hängt den Snapshot an die Tagesdatei von heute an
+///
+/// # Arguments
+/// * `client` - Eingeloggter Untis Client
+/// * `config` - Konfiguration des Programms
+pub(crate) fn scrape_with_client(client: &mut Client, config: &Config) -> Result<()> {
+    let (snapshot, master_data) = capture_snapshot(client, config)?;
+    store_snapshot(config, &snapshot, master_data.as_ref())
+}
+
+/// Ruft mit einem eingeloggten Client die Stammdaten und den Stundenplan ab, ohne etwas zu speichern. Schlägt der Abruf fehl,
+/// kann er daher gefahrlos wiederholt werden.
+///
+/// # Arguments
+/// * `client` - Eingeloggter Untis Client
+/// * `config` - Konfiguration des Programms
+///
+/// # Returns
+/// * `(Snapshot, Option<MasterData>)` - Snapshot des Stundenplans und die Stammdaten, falls sie abgerufen werden konnten
+pub(crate) fn capture_snapshot(client: &mut Client, config: &Config) -> Result<(Snapshot, Option<MasterData>)> {
+    // Jeder Durchlauf hat ein eigenes Budget für Wiederholungen, auch wenn die Sitzung weiterverwendet wird
+    client.reset_retry_budget();
+
+    // Wählt den Schlüssel für die Pseudonymisierung, der heute gilt
+    let pseudonymizer = Pseudonymizer::for_date(config, Local::now().date_naive())
+        .map_err(|e| anyhow::anyhow!("Kein Schlüssel für die Pseudonymisierung. {:#?}", e))?;
+
+    // Ruft bei Bedarf die Lehrerliste für das Bereinigen der Freitexte ab
+    let anonymizer = Anonymizer::new(client, config, pseudonymizer);
+
+    // Ergänzt den Tresor um neue Pseudonyme, ohne Tresor wird der Snapshot trotzdem gespeichert
+    record_vault(config, &anonymizer);
+
+    // Ruft die Stammdaten ab, ohne Stammdaten wird der Snapshot trotzdem gespeichert
+    let master_data = fetch_master_data(client, &anonymizer);
+
+    let snapshot = create_snapshot(client, config, &anonymizer, master_data.as_ref())
+        .map_err(|e| anyhow::anyhow!("Fehler beim erstellen des Snapshots. {:#?}", e))?;
+    Ok((snapshot, master_data))
+}
+
+/// Hängt einen Snapshot an die Tagesdatei von heute an. Schlägt das fehl, darf nicht erneut abgerufen und angehängt werden,
+/// da der Snapshot bereits teilweise geschrieben sein kann.
+///
+/// # Arguments
+/// * `config` - Konfiguration mit dem Pfad des Speichers
+/// * `snapshot` - Snapshot der angehängt werden soll
+/// * `master_data` - Stammdaten auf die der Snapshot verweist
+pub(crate) fn store_snapshot(config: &Config, snapshot: &Snapshot, master_data: Option<&MasterData>) -> Result<()> {
+    storage::append_snapshot(&config.path, Local::now().date_naive(), snapshot, master_data)
+        .map_err(|e| anyhow::anyhow!("Fehler beim Speichern des Snapshots. {:#?}", e))
+}
+
+/// Ruft den Stundenplan vergangener Tage ab und hängt je Tag einen als `Backfill` markierten Snapshot an die Tagesdatei des Tages an.
+/// Wochenenden und Ferien werden übersprungen. Schlägt ein Tag fehl, werden die übrigen Tage trotzdem abgerufen.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `from` - Erster Tag der abgerufen werden soll
+/// * `to` - Letzter Tag der abgerufen werden soll
+///
+/// # Returns
+/// * `Err` - Wenn der Login oder mindestens ein Tag fehlgeschlagen ist
+pub fn backfill(config: &Config, from: NaiveDate, to: NaiveDate) -> Result<()> {
+    if from > to {
+        anyhow::bail!("Der erste Tag {} liegt nach dem letzten Tag {}", from, to);
+    }
+
+    let mut client = login(config)?;
+
+    // Die Ferien sind nur für das aktuelle Schuljahr bekannt, daher werden frühere Schuljahre nicht übersprungen
+    let calendar = Calendar::fetch(&mut client).unwrap_or_else(|e| {
+        warn!("Ferien konnten nicht abgerufen werden, Ferientage werden ebenfalls abgerufen. {:#?}", e);
+        Calendar::default()
+    });
+
+    // Die Stammdaten enthalten pseudonymisierte Lehrer, daher werden sie (wie die Lehrerliste für das Bereinigen der Freitexte)
+    // je Schlüssel einmal abgerufen und in jeder Tagesdatei gespeichert, deren Tag mit diesem Schlüssel pseudonymisiert wird
+    let mut current: Option<(Anonymizer, Option<MasterData>)> = None;
+
+    // Beginnt mit dem ersten Schultag ab `from`
+    let mut date = add_school_days(from - chrono::Duration::days(1), 1);
+    let (mut succeeded, mut failed) = (0, 0);
+    while date <= to {
+        if let DayKind::Holiday(name) = calendar.classify(date) {
+            info!("{}: übersprungen, Ferien {}.", date, name);
+            date = add_school_days(date, 1);
+            continue;
+        }
+        // Jeder Tag hat ein eigenes Budget für Wiederholungen
+        client.reset_retry_budget();
+        let result = Pseudonymizer::for_date(config, date).and_then(|pseudonymizer| {
+            // Der Anonymizer des vorherigen Tages wird weiterverwendet, solange der Schlüssel gleich bleibt
+            let (anonymizer, master_data) = match current.take() {
+                Some(entry) if entry.0.key_id() == pseudonymizer.key_id() => current.insert(entry),
+                _ => {
+                    let anonymizer = Anonymizer::new(&mut client, config, pseudonymizer);
+                    record_vault(config, &anonymizer);
+                    let master_data = fetch_master_data(&mut client, &anonymizer);
+                    current.insert((anonymizer, master_data))
+                }
+            };
+            let snapshot = create_backfill_snapshot(&mut client, config, anonymizer, date, master_data.as_ref())?;
+            storage::append_snapshot(&config.path, date, &snapshot, master_data.as_ref())?;
+            Ok(snapshot.lessons().len())
+        });
+        match result {
+            Ok(count) => {
+                succeeded += 1;
+                info!("{}: {} Unterrichtsstunden nachträglich gespeichert.", date, count);
+            }
+            Err(e) => {
+                failed += 1;
+                error!("{}: Fehler beim nachträglichen Abrufen. {:#?}", date, e);
+            }
+        }
+        date = add_school_days(date, 1);
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} von {} Tagen konnten nicht abgerufen werden", failed, succeeded + failed);
+    }
+    info!("{} Tage wurden erfolgreich nachträglich abgerufen.", succeeded);
+    Ok(())
+}
+
+/// Ruft die Stammdaten ab. Schlägt der Abruf fehl, wird eine Warnung geloggt, da Snapshots auch ohne Stammdaten gespeichert werden.
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem die Daten abgerufen werden sollen
+/// * `anonymizer` - Anonymizer mit der Datenschutz Richtlinie
+fn fetch_master_data(client: &mut Client, anonymizer: &Anonymizer) -> Option<MasterData> {
+    match MasterData::fetch(client, anonymizer) {
+        Ok(master_data) => Some(master_data),
+        Err(e) => {
+            warn!("Stammdaten konnten nicht abgerufen werden. {:#?}", e);
+            None
+        }
+    }
+}
+
+/// Hängt die Pseudonyme aller Lehrer an den Tresor an, wenn `VAULT_PUBLIC_KEY` gesetzt ist.
+/// Fehler werden nur geloggt, da der Snapshot auch ohne Tresor gespeichert werden soll.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `anonymizer` - Anonymizer des Tages mit dem Pseudonymizer und der bereits abgerufenen Lehrerliste
+fn record_vault(config: &Config, anonymizer: &Anonymizer) {
+    let Some(public_key) = &config.vault_public_key else {
+        return;
+    };
+    let result = anonymizer
+        .teachers()
+        .ok_or_else(|| anyhow::anyhow!("Die Lehrerliste konnte nicht abgerufen werden"))
+        .and_then(|teachers| vault::record(&config.path, public_key, anonymizer.pseudonymizer(), teachers));
+    match result {
+        Ok(0) => {}
+        Ok(count) => info!("{} neue Pseudonyme wurden im Tresor gespeichert.", count),
+        Err(e) => warn!("Der Tresor konnte nicht ergänzt werden. {:#?}", e),
+    }
+}
+
+/// Löst ein Pseudonym über den Tresor auf und gibt die Namen aus. Jede Abfrage wird im Audit Log protokolliert.
+///
+/// # Arguments
+/// * `storage_path` - Pfad des Speichers, in dem der Tresor und das Audit Log liegen
+/// * `secret_key` - Geheimer Schlüssel des Tresors
+/// * `pseudonym` - Pseudonym das aufgelöst werden soll
+/// * `reason` - Grund der Abfrage
+pub fn reveal(storage_path: &str, secret_key: &str, pseudonym: &str, reason: &str) -> Result<()> {
+    let entries = vault::reveal(storage_path, secret_key, pseudonym, reason)?;
+    if entries.is_empty() {
+        println!("{}: nicht im Tresor enthalten", pseudonym);
+    }
+    for (entry, identity) in entries {
+        println!("{} (Schlüssel {}, seit {}): {} ({})", entry.pseudonym, entry.key_id, entry.recorded, identity.long_name, identity.name);
+    }
+    Ok(())
+}
+
+/// Erzeugt ein neues Schlüsselpaar für den Tresor. Der öffentliche Schlüssel wird ausgegeben, der geheime Schlüssel wird
+/// in eine neue Datei geschrieben, die nur der eigene Benutzer lesen kann, damit er nicht in der Ausgabe oder Terminal Logs landet.
+///
+/// # Arguments
+/// * `secret_key_file` - Datei für den geheimen Schlüssel, darf noch nicht existieren
+pub fn vault_keygen(secret_key_file: &Path) -> Result<()> {
+    let (public_key, secret_key) = vault::generate_keys();
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(secret_key_file)
+        .map_err(|e| anyhow::anyhow!("{} konnte nicht angelegt werden: {}", secret_key_file.display(), e))?;
+    writeln!(file, "VAULT_SECRET_KEY={}", secret_key)?;
+    file.sync_all()?;
+
+    println!("VAULT_PUBLIC_KEY={}", public_key);
+    eprintln!(
+        "Der geheime Schlüssel wurde in {} gespeichert. Die Datei gehört nicht auf den Server und sollte nach der Übergabe an die Schulleitung gelöscht werden.",
+        secret_key_file.display()
+    );
+    Ok(())
+}
+
+/// Erstellt die verschlüsselte Verknüpfungstabelle zwischen den Pseudonymen zweier Schlüssel für alle Lehrer,
+/// die aktuell in Untis hinterlegt sind, und speichert sie unter `STORAGE_PATH/linkage`.
+///
+/// # Arguments
+/// * `config` - Konfiguration des Programms
+/// * `from` - Id des alten Schlüssels, `legacy` für Pseudonyme vor der Einführung von HMAC
+/// * `to` - Id des neuen Schlüssels
+pub fn linkage(config: &Config, from: &str, to: &str) -> Result<()> {
+    let from = Pseudonymizer::for_key(config, from)?;
+    let to = Pseudonymizer::for_key(config, to)?;
+
+    let mut client = login(config)?;
+    let names: Vec<String> = client.call("Lehrer", |client| client.teachers())?.iter().map(|teacher| teacher.name.clone()).collect();
+
+    let linkage = Linkage::new(&from, &to, &names);
+    let path = linkage.write(&config.path, &from)?;
+    println!("{}: {} Lehrer von {} nach {} verknüpft", path.display(), linkage.entries.len(), linkage.from, linkage.to);
+    Ok(())
+}
+
+/// Gibt den Pfad der Tagesdatei zurück. Ist eine Datei angegeben wird diese verwendet, ansonsten die Tagesdatei des angegebenen Datums
+/// oder die Tagesdatei von heute.
+///
+/// # Arguments
+/// * `storage_path` - Pfad des Speichers, wird nur ohne angegebene Datei geladen
+/// * `date` - Datum der Tagesdatei
+/// * `file` - Pfad zu einer beliebigen Tagesdatei
+pub fn resolve_day_file(storage_path: impl FnOnce() -> Result<String>, date: Option<NaiveDate>, file: Option<&Path>) -> Result<PathBuf> {
+    match file {
+        Some(file) => Ok(file.to_path_buf()),
+        None => Ok(storage::day_path(&storage_path()?, date.unwrap_or_else(|| Local::now().date_naive()))),
+    }
+}
+
+/// Sucht alle Tagesdateien unterhalb von `STORAGE_PATH`
+///
+/// # Arguments
+/// * `storage_path` - Pfad des Speichers
+///
+/// # Returns
+/// * `Vec<PathBuf>` - Pfade aller `.bin` Dateien, sortiert nach Pfad
+pub fn day_files(storage_path: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut folders = vec![PathBuf::from(storage_path)];
+    while let Some(folder) = folders.pop() {
+        for entry in fs::read_dir(&folder)? {
+            let path = entry?.path();
+            // Tagesdateien liegen unter YYYY/M/D.bin, andere Dateien (z.B. Tresor oder Verknüpfungstabellen) werden ignoriert
+            let numeric = path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.parse::<u32>().is_ok());
+            if path.is_dir() && numeric {
+                folders.push(path);
+            } else if numeric && path.extension().is_some_and(|extension| extension == "bin") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Zählt die Unterrichtsstunden eines Snapshots je Art
+///
+/// # Returns
+/// * `(usize, usize, usize)` - Anzahl der regulären, unregelmäßigen und ausgefallenen Unterrichtsstunden
+fn count_codes(snapshot: &Snapshot) -> (usize, usize, usize) {
+    snapshot.iter().fold((0, 0, 0), |(regular, irregular, cancelled), lesson| match lesson.lesson_code {
+        LessonCode::Regular => (regular + 1, irregular, cancelled),
+        LessonCode::Irregular => (regular, irregular + 1, cancelled),
+        LessonCode::Cancelled => (regular, irregular, cancelled + 1),
+    })
+}
+
+/// Gibt die Snapshots einer Tagesdatei mit der Anzahl ihrer Unterrichtsstunden aus
+///
+/// # Arguments
+/// * `path` - Pfad der Tagesdatei
+pub fn inspect(path: &Path) -> Result<()> {
+    let export_file = ExportFile::read(path)?;
+    println!("Datei: {}", path.display());
+    println!("Snapshots: {}", export_file.snapshots().len());
+    for master_data in export_file.master_data() {
+        println!(
+            "Stammdaten {} ({}): {} Klassen, {} Räume, {} Fächer, {} Lehrer, {} Stunden im Raster, {} Ferien",
+            master_data.version(),
+            master_data.fetched(),
+            master_data.classes.len(),
+            master_data.rooms.len(),
+            master_data.subjects.len(),
+            master_data.teachers.len(),
+            master_data.timegrid.len(),
+            master_data.holidays.len()
+        );
+    }
+    for (index, snapshot) in export_file.iter().enumerate() {
+        let (regular, irregular, cancelled) = count_codes(snapshot);
+        let kind = match snapshot.kind() {
+            SnapshotKind::Live => "",
+            SnapshotKind::Backfill => " (nachträglich)",
+        };
+        println!(
+            "  [{}] {}{}: {} Unterrichtsstunden (regulär: {}, unregelmäßig: {}, ausgefallen: {}, zusammengeführte Duplikate: {}, Schlüssel: {}, Abrufdauer: {}, Stundenpläne: {})",
+            index,
+            snapshot.datetime(),
+            kind,
+            snapshot.lessons().len(),
+            regular,
+            irregular,
+            cancelled,
+            snapshot.folded_duplicates(),
+            snapshot.pseudonym_key().unwrap_or("-"),
+            snapshot.capture_duration().map_or_else(|| "-".to_string(), |duration| format!("{}s", duration.num_seconds())),
+            snapshot.run_metadata().map_or_else(|| "-".to_string(), |run| format!("{}/{}", run.succeeded, run.attempted))
+        );
+        if let Some(run) = snapshot.run_metadata() {
+            for failure in &run.failures {
+                let id = failure.element_id.map_or_else(|| "-".to_string(), |id| id.to_string());
+                println!("      fehlgeschlagen: {} {} ({:?})", failure.element_type, id, failure.error);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Exportiert Tagesdateien in das angegebene Format
+///
+/// # Arguments
+/// * `files` - Tagesdateien die exportiert werden sollen
+/// * `format` - Format der Ausgabe
+/// * `output` - Pfad der Ausgabedatei, ohne Pfad wird auf die Standardausgabe geschrieben.
+///   Bei Parquet und Arrow der Ordner in dem der Datensatz erstellt wird.
+pub fn export(files: &[PathBuf], format: ExportFormat, output: Option<&Path>) -> Result<()> {
+    // Spaltenorientierte Formate werden als partitionierter Datensatz in einen Ordner geschrieben
+    #[cfg(feature = "parquet")]
+    if let Some(columnar) = format.columnar() {
+        let output = output.ok_or_else(|| anyhow::anyhow!("Für das Format {} muss ein Ausgabeordner angegeben werden", format))?;
+        let count = crate::columnar::export_dataset(files, columnar, output)?;
+        info!("{} Tagesdateien mit {} Einträgen als {} exportiert.", files.len(), count, format);
+        return Ok(());
+    }
+
+    let writer: Box<dyn Write> = match output {
+        Some(output) => Box::new(BufWriter::new(fs::File::create(output)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let count = export::export_files(files, format, writer)?;
+    info!("{} Tagesdateien mit {} Einträgen als {} exportiert.", files.len(), count, format);
+    Ok(())
+}
+
+/// Validiert Tagesdateien. Es werden alle Einträge geprüft, ohne sie zu deserialisieren.
+/// Dateien im alten Format werden beim Prüfen in die aktuelle Version überführt, aber nicht gespeichert.
+///
+/// # Arguments
+/// * `paths` - Pfade der Tagesdateien die geprüft werden sollen
+///
+/// # Returns
+/// * `Err` - Wenn mindestens eine Datei beschädigt ist
+pub fn verify(paths: &[PathBuf]) -> Result<()> {
+    let mut failed = 0;
+    for path in paths {
+        match verify_file(path) {
+            Ok((count, FORMAT_VERSION)) => println!("OK      {} ({} Snapshots)", path.display(), count),
+            Ok((count, _)) => {
+                println!("OK      {} ({} Snapshots, altes Format, kann mit `migrate` überführt werden)", path.display(), count)
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FEHLER  {}: {}", path.display(), e);
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} von {} Dateien sind beschädigt", failed, paths.len());
+    }
+    Ok(())
+}
+
+/// Validiert alle Einträge einer Tagesdatei
+///
+/// # Returns
+/// * `(usize, u32)` - Anzahl der Snapshots in der Datei und Version der Datei
+fn verify_file(path: &Path) -> std::result::Result<(usize, u32), ArchiveError> {
+    match ArchiveReader::open(path) {
+        Ok(reader) => {
+            // Stammdaten werden genauso validiert wie Snapshots, aber nicht gezählt
+            for master_data in reader.master_data() {
+                master_data?;
+            }
+            let mut count = 0;
+            for snapshot in reader.snapshots() {
+                snapshot?;
+                count += 1;
+            }
+            Ok((count, FORMAT_VERSION))
+        }
+        // Das alte Format kann nicht ohne Kopieren gelesen werden und wird beim Lesen überführt und validiert
+        Err(ArchiveError::UnsupportedVersion { version: 0, .. }) => {
+            let mut count = 0;
+            for snapshot in SnapshotReader::open(path)? {
+                snapshot?;
+                count += 1;
+            }
+            Ok((count, 0))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Überführt Tagesdateien im alten Format in die aktuelle Version
+///
+/// # Arguments
+/// * `paths` - Pfade der Tagesdateien die überführt werden sollen
+pub fn migrate(paths: &[PathBuf]) -> Result<()> {
+    let mut migrated = 0;
+    for path in paths {
+        if let Some(version) = storage::migrate(path)? {
+            migrated += 1;
+            println!("{}: Version {} -> {}", path.display(), version, FORMAT_VERSION);
+        }
+    }
+    info!("{} von {} Dateien wurden in Version {} überführt.", migrated, paths.len(), FORMAT_VERSION);
+    Ok(())
+}
+
+/// Vergleicht zwei Snapshots einer Tagesdatei
+///
+/// # Arguments
+/// * `path` - Pfad der Tagesdatei
+/// * `old` - Index des älteren Snapshots, standardmäßig der Snapshot vor `new`
+/// * `new` - Index des neueren Snapshots, standardmäßig der letzte Snapshot
+///
+/// # Returns
+/// * `Err` - Wenn die Datei weniger als zwei Snapshots enthält oder `old` nicht vor `new` liegt
+pub fn diff(path: &Path, old: Option<usize>, new: Option<usize>) -> Result<()> {
+    let export_file = ExportFile::read(path)?;
+    let snapshots = export_file.snapshots();
+    if snapshots.len() < 2 {
+        anyhow::bail!("Die Datei enthält nur {} Snapshots", snapshots.len());
+    }
+    let new = new.unwrap_or(snapshots.len() - 1);
+    let old = match old {
+        Some(old) => old,
+        None => new.checked_sub(1).ok_or_else(|| anyhow::anyhow!("Vor Snapshot {} gibt es keinen älteren Snapshot", new))?,
+    };
+    if old >= new {
+        anyhow::bail!("Der ältere Snapshot ({}) muss vor dem neueren Snapshot ({}) liegen", old, new);
+    }
+    let (Some(old_snapshot), Some(new_snapshot)) = (snapshots.get(old), snapshots.get(new)) else {
+        anyhow::bail!("Die Datei enthält nur {} Snapshots", snapshots.len());
+    };
+
+    println!("[{}] -> [{}]", old, new);
+    print!("{}", diff::diff(old_snapshot, new_snapshot)?);
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use std::{env, fmt, str::FromStr};
+
+use crate::{privacy::PrivacyPolicy, pseudonym::PseudonymKey};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Debug)]
+/// Config repräsentiert die Konfiguration die aus der .env Datei geladen wird.
+pub struct Config {
+    /// Server auf dem Untis läuft
+    pub server: String,
+    /// Schule für die der Stundenplan abgerufen werden soll
+    pub school: String,
+    /// Benutzername für den Untis Account
+    pub user: String,
+    /// Passwort für den Untis Account
+    pub password: String,
+    /// Secret für die Pseudonymisierung der Lehrernamen mit dem alten Verfahren, wenn keine Schlüssel in `PSEUDONYM_KEYS`
+    /// konfiguriert sind, und für Verknüpfungstabellen von `legacy`
+    pub secret: String,
+    /// Pfad an dem die Daten gespeichert werden sollen
+    pub path: String,
+    /// Pfad an dem die Status Datei gespeichert werden soll
+    pub state_file_path: Option<String>,
+    /// URL unter der die Status Datei abgerufen werden kann
+    pub state_file_check: Option<String>,
+    /// Anzahl der Schultage vor heute, deren Stundenplan abgerufen wird
+    pub fetch_days_before: u32,
+    /// Anzahl der Schultage nach heute, deren Stundenplan abgerufen wird
+    pub fetch_days_ahead: u32,
+    /// Elementtypen deren Stundenpläne abgerufen werden
+    pub element_types: Vec<ElementKind>,
+    /// Anzahl der Worker, die die Stundenpläne gleichzeitig abrufen, jeder Worker loggt sich mit einem eigenen Client ein
+    pub fetch_workers: usize,
+    /// Zeit in Sekunden, nach der eine Anfrage an Untis abgebrochen wird
+    pub request_timeout: u64,
+    /// Höchste Anzahl an Wiederholungen einer fehlgeschlagenen Anfrage
+    pub max_retries: u32,
+    /// Höchste Anzahl an Wiederholungen aller Anfragen eines Durchlaufs
+    pub retry_budget: u32,
+    /// Wartezeit vor der ersten Wiederholung in Millisekunden, verdoppelt sich mit jeder Wiederholung
+    pub retry_base_delay: u64,
+    /// Höchste Wartezeit vor einer Wiederholung in Millisekunden
+    pub retry_max_delay: u64,
+    /// Höchste Anzahl an Anfragen pro Sekunde über alle Worker, `0` ohne Begrenzung
+    pub requests_per_second: f64,
+    /// Schlüssel für die Pseudonymisierung, jeweils gültig ab einem Datum
+    pub pseudonym_keys: Vec<PseudonymKey>,
+    /// Datenschutz Richtlinie für die Felder der Unterrichtsstunden
+    pub privacy_policy: PrivacyPolicy,
+    /// Öffentlicher Schlüssel des Tresors für die Re-Identifizierung, ohne Schlüssel wird kein Tresor geschrieben
+    pub vault_public_key: Option<String>,
+    /// Zeitpunkte der Durchläufe im Daemon Modus als cron Ausdrücke
+    pub schedule: Vec<String>,
+    /// Höchste zufällige Verzögerung eines Durchlaufs im Daemon Modus in Sekunden
+    pub schedule_jitter: u64,
+}
+
+/// Zeitplan des Daemon Modus, wenn `SCHEDULE` nicht gesetzt ist
+const DEFAULT_SCHEDULE: &str = "0 2,6,8,20 * * *";
+/// Zulässiger Bereich für `REQUESTS_PER_SECOND`, außer `0` ohne Begrenzung
+const REQUESTS_PER_SECOND_RANGE: std::ops::RangeInclusive<f64> = 0.001..=1000.0;
+/// Zulässiger Bereich für `REQUEST_TIMEOUT` in Sekunden
+const REQUEST_TIMEOUT_RANGE: std::ops::RangeInclusive<u64> = 1..=3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'ElementKind' repräsentiert die Elementtypen, deren Stundenpläne in Untis abgerufen werden können
+pub enum ElementKind {
+    /// Stundenpläne der Klassen
+    Class,
+    /// Stundenpläne der Lehrer, enthalten auch Unterrichtsstunden ohne Klasse (z.B. Aufsichten oder Konferenzen)
+    Teacher,
+    /// Stundenpläne der Räume, enthalten auch Raumbuchungen ohne Klasse
+    Room,
+    /// Stundenpläne der Fächer
+    Subject,
+}
+
+impl FromStr for ElementKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "class" => Ok(ElementKind::Class),
+            "teacher" => Ok(ElementKind::Teacher),
+            "room" => Ok(ElementKind::Room),
+            "subject" => Ok(ElementKind::Subject),
+            _ => Err(format!("Unbekannter Elementtyp \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for ElementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElementKind::Class => write!(f, "class"),
+            ElementKind::Teacher => write!(f, "teacher"),
+            ElementKind::Room => write!(f, "room"),
+            ElementKind::Subject => write!(f, "subject"),
+        }
+    }
+}
+
+
+/// Lädt die .env Datei und überschreibt die bereits gesetzten Variablen
+pub fn load_dotenv(){
+    // Lädt die .env Datei, wenn sie nicht gefunden wird wird eine Fehlermeldung ausgegeben.
+    if dotenvy::dotenv_override().is_err() {
+        println!("Failed to load \".env\" file.");
+    }
+}
+
+/// Lädt die Konfiguration aus der .env Datei
+pub fn load_config() -> Result<Config> {
+
+    // Lädt die Variablen aus der .env Datei, wenn eine Variable nicht gefunden wird, wird ein Fehler zurückgegeben.
+    Ok(Config {
+        server: required_var("SERVER")?,
+        school: required_var("SCHOOL")?,
+        user: required_var("USERNAME")?,
+        password: required_var("PASSWORD")?,
+        secret: required_var("SECRET")?,
+        path: load_storage_path()?,
+        state_file_path: env::var("STATE_PATH").ok(),
+        state_file_check: env::var("STATE_CHECK_URL").ok(),
+        fetch_days_before: parse_var("FETCH_DAYS_BEFORE")?.unwrap_or(0),
+        fetch_days_ahead: parse_var("FETCH_DAYS_AHEAD")?.unwrap_or(0),
+        element_types: parse_list("ELEMENT_TYPES")?.unwrap_or_else(|| vec![ElementKind::Class]),
+        fetch_workers: parse_var("FETCH_WORKERS")?.unwrap_or(1).max(1),
+        request_timeout: check_request_timeout(parse_var("REQUEST_TIMEOUT")?.unwrap_or(60))?,
+        max_retries: parse_var("MAX_RETRIES")?.unwrap_or(3),
+        retry_budget: parse_var("RETRY_BUDGET")?.unwrap_or(50),
+        retry_base_delay: parse_var("RETRY_BASE_DELAY")?.unwrap_or(500),
+        retry_max_delay: parse_var("RETRY_MAX_DELAY")?.unwrap_or(30_000),
+        requests_per_second: check_requests_per_second(parse_var("REQUESTS_PER_SECOND")?.unwrap_or(5.0))?,
+        pseudonym_keys: parse_list("PSEUDONYM_KEYS")?.unwrap_or_default(),
+        privacy_policy: PrivacyPolicy::from_rules(&parse_list("PRIVACY_POLICY")?.unwrap_or_default())
+            .map_err(|e| anyhow::anyhow!("Variable PRIVACY_POLICY ist ungültig: {}", e))?,
+        vault_public_key: env::var("VAULT_PUBLIC_KEY").ok(),
+        // cron Ausdrücke enthalten Kommas, daher werden sie durch Semikolons getrennt
+        schedule: env::var("SCHEDULE")
+            .map(|value| value.split(';').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect())
+            .unwrap_or_else(|_| vec![DEFAULT_SCHEDULE.to_string()]),
+        schedule_jitter: parse_var("SCHEDULE_JITTER")?.unwrap_or(0),
+    })
+}
+
+/// Lädt nur den Pfad des Speichers, für Befehle die lediglich die gespeicherten Daten lesen und keinen Zugang zu Untis benötigen
+pub fn load_storage_path() -> Result<String> {
+    required_var("STORAGE_PATH")
+}
+
+/// Lädt den geheimen Schlüssel des Tresors, er wird nur für `reveal` benötigt und ist daher nicht Teil der `Config`
+pub fn load_vault_secret_key() -> Result<String> {
+    required_var("VAULT_SECRET_KEY")
+}
+
+/// Prüft die Anzahl der Anfragen pro Sekunde, aus der der Mindestabstand zwischen zwei Anfragen berechnet wird
+///
+/// # Arguments
+/// * `value` - Wert von `REQUESTS_PER_SECOND`
+///
+/// # Returns
+/// * `Err` - Wenn der Wert weder `0` ist noch in `REQUESTS_PER_SECOND_RANGE` liegt (auch bei `NaN` und unendlich)
+fn check_requests_per_second(value: f64) -> Result<f64> {
+    if value == 0.0 || REQUESTS_PER_SECOND_RANGE.contains(&value) {
+        Ok(value)
+    } else {
+        Err(anyhow::anyhow!(
+            "Variable REQUESTS_PER_SECOND ist ungültig: {} liegt nicht zwischen {} und {} und ist nicht 0",
+            value,
+            REQUESTS_PER_SECOND_RANGE.start(),
+            REQUESTS_PER_SECOND_RANGE.end()
+        ))
+    }
+}
+
+/// Prüft die Zeit, nach der eine Anfrage an Untis abgebrochen wird. Bei `0` würde jede Anfrage sofort abgebrochen.
+///
+/// # Arguments
+/// * `value` - Wert von `REQUEST_TIMEOUT` in Sekunden
+///
+/// # Returns
+/// * `Err` - Wenn der Wert nicht in `REQUEST_TIMEOUT_RANGE` liegt
+fn check_request_timeout(value: u64) -> Result<u64> {
+    if REQUEST_TIMEOUT_RANGE.contains(&value) {
+        Ok(value)
+    } else {
+        Err(anyhow::anyhow!(
+            "Variable REQUEST_TIMEOUT ist ungültig: {} liegt nicht zwischen {} und {} Sekunden",
+            value,
+            REQUEST_TIMEOUT_RANGE.start(),
+            REQUEST_TIMEOUT_RANGE.end()
+        ))
+    }
+}
+
+/// Lädt eine Variable die gesetzt sein muss
+///
+/// # Arguments
+/// * `name` - Name der Variable
+///
+/// # Returns
+/// * `Err` - Wenn die Variable nicht gesetzt ist, der Fehler enthält den Namen der Variable
+fn required_var(name: &str) -> Result<String> {
+    env::var(name).map_err(|e| anyhow::anyhow!("Variable {} ist nicht gesetzt: {}", name, e))
+}
+
+/// Lädt eine optionale Variable und wandelt sie in den angegebenen Typ um
+///
+/// # Arguments
+/// * `name` - Name der Variable
+///
+/// # Returns
+/// * `None` - Wenn die Variable nicht gesetzt ist
+/// * `Err` - Wenn die Variable nicht umgewandelt werden kann
+fn parse_var<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) => Ok(Some(value.parse().map_err(|e| anyhow::anyhow!("Variable {} ist ungültig: {}", name, e))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Lädt eine optionale, durch Kommas getrennte Liste und wandelt jeden Eintrag in den angegebenen Typ um
+///
+/// # Arguments
+/// * `name` - Name der Variable
+///
+/// # Returns
+/// * `None` - Wenn die Variable nicht gesetzt ist
+/// * `Err` - Wenn ein Eintrag nicht umgewandelt werden kann
+fn parse_list<T>(name: &str) -> Result<Option<Vec<T>>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => Ok(Some(
+            value
+                .split(',')
+                .filter(|item| !item.trim().is_empty())
+                .map(|item| item.parse().map_err(|e| anyhow::anyhow!("Variable {} ist ungültig: {}", name, e)))
+                .collect::<Result<Vec<T>>>()?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_per_second_accepts_zero_and_the_range() {
+        assert_eq!(check_requests_per_second(0.0).unwrap(), 0.0);
+        assert_eq!(check_requests_per_second(0.001).unwrap(), 0.001);
+        assert_eq!(check_requests_per_second(5.0).unwrap(), 5.0);
+        assert_eq!(check_requests_per_second(1000.0).unwrap(), 1000.0);
+    }
+
+    #[test]
+    fn requests_per_second_rejects_values_without_an_interval() {
+        for value in [-1.0, 1e-300, 1000.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(check_requests_per_second(value).is_err(), "{} wurde akzeptiert", value);
+        }
+    }
+
+    #[test]
+    fn request_timeout_must_be_in_range() {
+        assert_eq!(check_request_timeout(1).unwrap(), 1);
+        assert_eq!(check_request_timeout(60).unwrap(), 60);
+        assert_eq!(check_request_timeout(3600).unwrap(), 3600);
+        for value in [0, 3601, u64::MAX] {
+            assert!(check_request_timeout(value).is_err(), "{} wurde akzeptiert", value);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Daemon Modus mit Zeitplan.
+//!
+//! Der Daemon läuft dauerhaft und startet Durchläufe zu den Zeitpunkten aus `SCHEDULE` (cron Ausdrücke, durch `;` getrennt).
+//! Jeder Durchlauf wird um eine zufällige Verzögerung von bis zu `SCHEDULE_JITTER` Sekunden verschoben. An Wochenenden, in
+//! den Ferien und außerhalb des Schuljahrs wird nichts abgerufen, der Grund wird als Status SKIPPED gespeichert. Die Untis
+//! Sitzung wird mit den Sitzungen der Worker zwischen den Durchläufen weiterverwendet und nur nach einem Fehler neu aufgebaut.
+
+use std::{str::FromStr, thread, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use cron::Schedule;
+use log::{info, warn};
+use rand::Rng;
+
+use crate::{
+    calendar::Calendar,
+    commands::{capture_snapshot, finish_run, login, primary_has_run, set_state, skip_non_school_day, store_snapshot},
+    config::Config,
+    request::Client,
+    state::State,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Längste Zeit die am Stück geschlafen wird, damit Sprünge der Systemuhr bemerkt werden
+const MAX_SLEEP: StdDuration = StdDuration::from_secs(60);
+
+/// Liest einen cron Ausdruck. Ausdrücke mit 5 Feldern (wie in der crontab) werden um die Sekunde `0` ergänzt.
+///
+/// # Arguments
+/// * `expression` - cron Ausdruck, z.B. `0 2,6,8,20 * * *`
+pub fn parse_schedule(expression: &str) -> Result<Schedule> {
+    let expression = expression.trim();
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    Schedule::from_str(&expression).map_err(|e| anyhow::anyhow!("Ungültiger Zeitplan \"{}\": {}", expression, e))
+}
+
+/// 'Session' hält die Untis Sitzung und den Kalender zwischen den Durchläufen
+#[derive(Default)]
+struct Session {
+    /// Eingeloggter Client mit seinen Workern, `None` bis zum ersten Login oder nach einem Fehler
+    client: Option<Client>,
+    /// Kalender und der Tag an dem er abgerufen wurde
+    calendar: Option<(NaiveDate, Calendar)>,
+}
+
+impl Session {
+    /// Gibt den eingeloggten Client zurück und loggt sich bei Bedarf neu ein
+    fn client(&mut self, config: &Config) -> Result<&mut Client> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => login(config)?,
+        };
+        Ok(self.client.insert(client))
+    }
+
+    /// Gibt den Kalender des Tages zurück. Der Kalender wird einmal am Tag abgerufen, schlägt das fehl, wird `None`
+    /// zurückgegeben und der Tag als Schultag behandelt, damit kein Durchlauf verloren geht.
+    fn calendar(&mut self, config: &Config, date: NaiveDate) -> Option<&Calendar> {
+        if self.calendar.as_ref().map(|(fetched, _)| *fetched) != Some(date) {
+            match self.client(config).and_then(Calendar::fetch) {
+                Ok(calendar) => self.calendar = Some((date, calendar)),
+                Err(e) => {
+                    warn!("Ferien konnten nicht abgerufen werden, es wird trotzdem abgerufen. {:#?}", e);
+                    return None;
+                }
+            }
+        }
+        self.calendar.as_ref().map(|(_, calendar)| calendar)
+    }
+}
+
+/// Startet den Daemon. Die Funktion kehrt nur zurück, wenn der Zeitplan ungültig ist.
+///
+/// # Arguments
+/// * `config` - Konfiguration mit dem Zeitplan
+pub fn run(config: &Config) -> Result<()> {
+    let schedules = config.schedule.iter().map(|expression| parse_schedule(expression)).collect::<Result<Vec<_>>>()?;
+    if schedules.is_empty() {
+        anyhow::bail!("SCHEDULE enthält keinen Zeitplan");
+    }
+    info!("Daemon wurde mit {} Zeitplänen gestartet.", schedules.len());
+
+    let mut session = Session::default();
+    loop {
+        let Some(next) = schedules.iter().filter_map(|schedule| schedule.upcoming(Local).next()).min() else {
+            anyhow::bail!("Der Zeitplan enthält keine weiteren Zeitpunkte");
+        };
+        let start = next + jitter(config.schedule_jitter);
+        info!("Nächster Durchlauf um {}.", start);
+        sleep_until(start);
+        run_once(config, &mut session);
+    }
+}
+
+/// Führt einen Durchlauf mit der bestehenden Sitzung aus. Schlägt der Abruf fehl, z.B. weil die Sitzung abgelaufen ist,
+/// wird er einmal mit einem neuen Login wiederholt. Das Anhängen an die Tagesdatei wird nie wiederholt, damit ein teilweise
+/// geschriebener Snapshot nicht ein zweites Mal gespeichert wird.
+fn run_once(config: &Config, session: &mut Session) {
+    // Wochenenden werden ohne Login übersprungen
+    let today = Local::now().date_naive();
+    if skip_non_school_day(config, &Calendar::default(), today) {
+        return;
+    }
+    if primary_has_run(config) {
+        return;
+    }
+    if session.calendar(config, today).is_some_and(|calendar| skip_non_school_day(config, calendar, today)) {
+        return;
+    }
+
+    set_state(config, State::STARTED);
+    let captured = match session.client(config).and_then(|client| capture_snapshot(client, config)) {
+        Err(e) if session.client.is_some() => {
+            warn!("Abruf fehlgeschlagen, er wird mit einem neuen Login wiederholt. {:#?}", e);
+            session.client = None;
+            session.client(config).and_then(|client| capture_snapshot(client, config))
+        }
+        result => result,
+    };
+    if captured.is_err() {
+        // Nach einem Fehler beim Abruf wird beim nächsten Durchlauf neu eingeloggt
+        session.client = None;
+    }
+    finish_run(config, captured.and_then(|(snapshot, master_data)| store_snapshot(config, &snapshot, master_data.as_ref())));
+}
+
+/// Gibt eine zufällige Verzögerung zwischen 0 und `max_seconds` Sekunden zurück
+fn jitter(max_seconds: u64) -> Duration {
+    if max_seconds == 0 {
+        return Duration::zero();
+    }
+    Duration::seconds(rand::thread_rng().gen_range(0..=max_seconds) as i64)
+}
+
+/// Schläft bis zum angegebenen Zeitpunkt
+fn sleep_until(time: DateTime<Local>) {
+    while let Ok(remaining) = (time - Local::now()).to_std() {
+        if remaining.is_zero() {
+            break;
+        }
+        thread::sleep(remaining.min(MAX_SLEEP));
+    }
+}
@@ -1,124 +1,534 @@
+use std::{collections::HashMap, path::Path};
 
-use std::{path::Path, fs::File, io::{BufReader, Read}};
+use chrono::{DateTime, Utc, NaiveDate, NaiveTime};
+use rkyv::{Archive,Serialize,Deserialize, AlignedVec, check_archived_root};
 
-use chrono::{DateTime, Utc, Local, Datelike};
-use rkyv::{Archive,Serialize,Deserialize, archived_root, ser::{serializers::AllocSerializer, Serializer}};
+use crate::{
+    master_data::MasterData,
+    request::ErrorKind,
+    storage::{self, ArchiveError, SnapshotReader},
+};
 
-type Result<T> = anyhow::Result<T>;
+pub(crate) mod migrate;
 
 /// 'ExportFile' repräsentiert die Datei in der die Rohdaten gespeichert werden. 
-#[derive(Archive,Serialize,Deserialize,Debug)]
+#[derive(Debug,serde::Serialize)]
 pub struct ExportFile {
     /// Datum der Exportieren Daten
     date: DateTime<Utc>,
     /// Snapshots des Stundenplans in der Exportieren Datei
     snapshots: Vec<Snapshot>, 
+    /// Stammdaten auf die die Snapshots verweisen, werden als eigene Einträge im Snapshot Log gespeichert
+    master_data: Vec<MasterData>,
 }
 
 impl ExportFile{
-    /// Lädt die Exportierte Datei aus dem angegebenen Pfad. Wenn die Datei nicht existiert wird eine neue Datei erstellt.
+    /// Liest eine Exportierte Datei. Es werden sowohl Snapshot Logs als auch Dateien im alten Format gelesen,
+    /// in denen das gesamte ExportFile am Stück gespeichert ist.
+    ///
     /// # Arguments
-    /// * `path` - Pfad an dem die Datei gespeichert werden soll
-    /// 
+    /// * `path` - Pfad der Datei die gelesen werden soll
+    ///
     /// # Returns
     /// * `ExportFile` - Exportierte Datei
-    pub fn load(path: &str) -> Result<Self>{
-        // Ruft das aktuelle Datum ab
-        let now = Local::now();
-        // Erzeugt den Pfad an dem die Datei gespeichert werden soll
-        let folder = format!("{}/{}/{}/", path,  now.year(),now.month()); 
-        let full_path = format!("{}/{}.bin", folder,   now.day());
-        // Überprüft ob die Datei existiert
-        if Path::new(&full_path).exists(){
-            // Lädt die Datei
-            if let Ok(file) = File::open(full_path){
-
-                // Liest die Datei in einen Buffer
-                let mut buf_reader = BufReader::new(file);
-                let mut buffer = Vec::new();
-                let _ = buf_reader.read_to_end(&mut buffer);
-                // Lädt die Datei aus dem Buffer
-                let archived = unsafe { archived_root::<Self>(&buffer) };
-
-                // Deserialisiert die Datei
-                return  Ok(archived.deserialize(&mut rkyv::de::deserializers::SharedDeserializeMap::default())?);
+    /// * `ArchiveError::Corrupt` - Wenn die Datei nicht validiert werden konnte
+    pub fn read(path: &Path) -> Result<Self, ArchiveError> {
+        let snapshots = SnapshotReader::open(path)?.collect::<Result<Vec<Snapshot>, ArchiveError>>()?;
+        let master_data = storage::read_master_data(path)?;
+        // Das Datum der Datei entspricht dem Zeitpunkt des ersten Snapshots
+        let date = snapshots.first().map(|snapshot| snapshot.datetime).unwrap_or_else(Utc::now);
+        Ok(Self { date, snapshots, master_data })
+    }
 
+    /// Liest und validiert eine Exportierte Datei im alten Format (Version 0), in dem das gesamte ExportFile am Stück gespeichert ist,
+    /// und überführt sie in die aktuelle Version.
+    ///
+    /// # Arguments
+    /// * `path` - Pfad der Datei die gelesen werden soll
+    ///
+    /// # Returns
+    /// * `ExportFile` - Exportierte Datei
+    /// * `ArchiveError::Corrupt` - Wenn die Datei nicht validiert werden konnte
+    pub fn read_legacy(path: &Path) -> Result<Self, ArchiveError> {
+        // Liest die Datei in einen Buffer, der für rkyv korrekt ausgerichtet ist
+        let bytes = std::fs::read(path)?;
+        let mut buffer = AlignedVec::with_capacity(bytes.len());
+        buffer.extend_from_slice(&bytes);
 
-            }
-        }else{
-            // Erstellt den Ordner in dem die Datei gespeichert werden soll
-            std::fs::create_dir_all(folder).unwrap();
-        } 
+        // Validiert die Datei bevor auf sie zugegriffen wird
+        let corrupt = |reason: String| ArchiveError::Corrupt { path: path.display().to_string(), reason };
+        let archived = check_archived_root::<migrate::ExportFileV0>(&buffer).map_err(|e| corrupt(e.to_string()))?;
 
-        // Gib das ExportFile struct zurück
-        Ok(Self { date: Utc::now(), snapshots: Vec::new() })
+        // Deserialisiert die Datei und überführt sie in die aktuelle Version
+        let export_file: migrate::ExportFileV0 = archived
+            .deserialize(&mut rkyv::de::deserializers::SharedDeserializeMap::default())
+            .map_err(|e| corrupt(format!("{:?}", e)))?;
+        Ok(Self {
+            date: export_file.date,
+            snapshots: export_file.snapshots.into_iter().map(Into::into).collect(),
+            master_data: Vec::new(),
+        })
     }
 
-    /// Speichert die Datei an dem angegebenen Pfad
-    /// 
-    /// # Arguments
-    /// * `path` - Pfad an dem die Datei gespeichert werden soll
-    /// 
-    pub fn save(self,path: &str) -> Result<()>{
-        // Ruft das aktuelle Datum ab und erstellt den Pfad an dem die Datei gespeichert werden soll
-        let now = Local::now();
-        let path = format!("{}/{}/{}/{}.bin", path,  now.year(),now.month(),now.day()); 
-        
-        // Erstellt einen Serializer
-        let mut serializer = AllocSerializer::<1024>::default();
-        
-        // Serialisiert das ExportFile struct
-        serializer.serialize_value(&self).unwrap();
-        let data = serializer.into_serializer().into_inner();
-        
-        // Speichert die Datei an dem angegebenen Pfad
-        std::fs::write(path, data)?;
-        Ok(())
-    }
-
-    /// Fügt einen Snapshot der ExportFile hinzu
-    /// 
-    /// # Arguments
-    /// * `snapshot` - Snapshot der hinzugefügt werden soll
-    /// 
-    pub fn add(&mut self, snapshot: Snapshot){
-        self.snapshots.push(snapshot);
+    /// Gibt das Datum der Exportierten Daten zurück
+    pub fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+
+    /// Gibt die Snapshots der ExportFile in der Reihenfolge zurück, in der sie aufgenommen wurden
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Gibt alle Versionen der Stammdaten zurück, die in der Datei gespeichert sind
+    pub fn master_data(&self) -> &[MasterData] {
+        &self.master_data
+    }
+
+    /// Gibt die Stammdaten zurück, die zum Zeitpunkt des Snapshots galten
+    ///
+    /// # Returns
+    /// * `None` - Wenn der Snapshot auf keine Stammdaten verweist oder diese nicht in der Datei gespeichert sind
+    pub fn master_data_for(&self, snapshot: &Snapshot) -> Option<&MasterData> {
+        let version = snapshot.master_data_version()?;
+        self.master_data.iter().find(|master_data| master_data.version() == version)
+    }
+
+    /// Gibt einen Iterator über die Snapshots der ExportFile zurück
+    pub fn iter(&self) -> std::slice::Iter<'_, Snapshot> {
+        self.snapshots.iter()
+    }
+
+    /// Gibt einen Iterator über alle Unterrichtsstunden aller Snapshots zusammen mit dem jeweiligen Snapshot zurück
+    pub fn lessons(&self) -> impl Iterator<Item = (&Snapshot, &Lesson)> {
+        self.snapshots.iter().flat_map(|snapshot| snapshot.lessons.iter().map(move |lesson| (snapshot, lesson)))
+    }
+
+    /// Gibt die Snapshots der ExportFile zurück
+    ///
+    /// # Returns
+    /// * `Vec<Snapshot>` - Snapshots in der Reihenfolge in der sie aufgenommen wurden
+    pub fn into_snapshots(self) -> Vec<Snapshot> {
+        self.snapshots
     }
 }
 
+impl<'a> IntoIterator for &'a ExportFile {
+    type Item = &'a Snapshot;
+    type IntoIter = std::slice::Iter<'a, Snapshot>;
 
-#[derive(Archive,Serialize,Deserialize,Debug)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.snapshots.iter()
+    }
+}
+
+impl IntoIterator for ExportFile {
+    type Item = Snapshot;
+    type IntoIter = std::vec::IntoIter<Snapshot>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.snapshots.into_iter()
+    }
+}
+
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,serde::Serialize)]
+#[archive(check_bytes)]
 /// 'Snapshot' ist eine Momentaufnahme des Stundenplans. 
 pub struct Snapshot {
     /// Datum mit Zeitpunkt des jeweiligen Snapshots
     datetime: DateTime<Utc>, 
+    /// Erster Tag des Zeitraums, dessen Stundenplan abgerufen wurde
+    window_start: NaiveDate,
+    /// Letzter Tag des Zeitraums, dessen Stundenplan abgerufen wurde
+    window_end: NaiveDate,
+    /// Gibt an ob der Snapshot live erfasst oder nachträglich abgerufen wurde
+    kind: SnapshotKind,
     /// Unterrichtstunden die zum Zeitpunkt des Snapshots auf den Stundenplan hinterlegt waren
     lessons: Vec<Lesson>, 
+    /// Anzahl der Unterrichtsstunden, die mehrfach abgerufen (z.B. bei mehreren Klassen) und zusammengeführt wurden
+    folded_duplicates: usize,
+    /// Version der Stammdaten, die zum Zeitpunkt des Snapshots galten
+    master_data_version: Option<String>,
+    /// Id des Schlüssels, mit dem die Lehrer pseudonymisiert wurden
+    pseudonym_key: Option<String>,
+    /// Zeitpunkt zu dem der erste Stundenplan abgerufen wurde, `None` bei Snapshots aus dem alten Format
+    capture_start: Option<DateTime<Utc>>,
+    /// Zeitpunkt zu dem der letzte Stundenplan empfangen wurde, `None` bei Snapshots aus dem alten Format
+    capture_end: Option<DateTime<Utc>>,
+    /// Metadaten des Durchlaufs, `None` bei Snapshots aus dem alten Format
+    run: Option<RunMetadata>,
+    /// Index der Unterrichtsstunden nach ihrer Identität, wird nicht gespeichert
+    #[with(rkyv::with::Skip)]
+    #[serde(skip)]
+    index: HashMap<LessonKey, usize>,
 }
 
 impl Snapshot {
     /// Erstellt einen neuen Snapshot
     /// 
+    /// # Arguments
+    /// * `window_start` - Erster Tag des Zeitraums, dessen Stundenplan abgerufen wird
+    /// * `window_end` - Letzter Tag des Zeitraums, dessen Stundenplan abgerufen wird
+    ///
     /// # Returns
     /// * `Snapshot` - Neuer Snapshot
-    pub fn new() -> Self {
-        Self { datetime: Utc::now(), lessons: Vec::new() }
+    pub fn new(window_start: NaiveDate, window_end: NaiveDate) -> Self {
+        Self {
+            datetime: Utc::now(),
+            window_start,
+            window_end,
+            kind: SnapshotKind::Live,
+            lessons: Vec::new(),
+            folded_duplicates: 0,
+            master_data_version: None,
+            pseudonym_key: None,
+            capture_start: None,
+            capture_end: None,
+            run: None,
+            index: HashMap::new(),
+        }
+    }
+
+    /// Erstellt einen neuen Snapshot, der nachträglich für vergangene Tage abgerufen wird
+    ///
+    /// # Arguments
+    /// * `window_start` - Erster Tag des Zeitraums, dessen Stundenplan abgerufen wird
+    /// * `window_end` - Letzter Tag des Zeitraums, dessen Stundenplan abgerufen wird
+    ///
+    /// # Returns
+    /// * `Snapshot` - Neuer Snapshot mit der Art `SnapshotKind::Backfill`
+    pub fn backfill(window_start: NaiveDate, window_end: NaiveDate) -> Self {
+        Self { kind: SnapshotKind::Backfill, ..Self::new(window_start, window_end) }
     }
     
-    /// Fügt eine Unterrichtsstunde dem Snapshot hinzu
+    /// Fügt eine Unterrichtsstunde dem Snapshot hinzu. Ist die Unterrichtsstunde (gleiche Id, gleiches Datum und gleicher Beginn)
+    /// bereits enthalten, z.B. weil sie im Stundenplan mehrerer Klassen vorkommt, werden nur die Klassen zusammengeführt
+    /// und das Duplikat gezählt.
     /// 
     /// # Arguments
     /// * `lesson` - Unterrichtsstunde die hinzugefügt werden soll
     pub fn add_lesson(&mut self, lesson: Lesson){
-        self.lessons.push(lesson)
+        // Nach dem Deserialisieren ist der Index leer und wird neu aufgebaut
+        if self.index.len() != self.lessons.len() {
+            self.index = self.lessons.iter().enumerate().map(|(position, lesson)| (LessonKey::from(lesson), position)).collect();
+        }
+
+        let key = LessonKey::from(&lesson);
+        match self.index.get(&key).copied() {
+            Some(position) => {
+                let existing = &mut self.lessons[position];
+                for class in lesson.classes {
+                    if !existing.classes.contains(&class) {
+                        existing.classes.push(class);
+                    }
+                }
+                self.folded_duplicates += 1;
+            }
+            None => {
+                self.index.insert(key, self.lessons.len());
+                self.lessons.push(lesson);
+            }
+        }
+    }
+
+    /// Gibt den Zeitpunkt des Snapshots zurück
+    pub fn datetime(&self) -> DateTime<Utc> {
+        self.datetime
+    }
+
+    /// Gibt den Zeitraum zurück, dessen Stundenplan abgerufen wurde
+    ///
+    /// # Returns
+    /// * `(NaiveDate, NaiveDate)` - Erster und letzter Tag des Zeitraums
+    pub fn window(&self) -> (NaiveDate, NaiveDate) {
+        (self.window_start, self.window_end)
+    }
+
+    /// Gibt zurück ob der Snapshot live erfasst oder nachträglich abgerufen wurde
+    pub fn kind(&self) -> SnapshotKind {
+        self.kind
+    }
+
+    /// Gibt die Unterrichtsstunden des Snapshots zurück
+    pub fn lessons(&self) -> &[Lesson] {
+        &self.lessons
+    }
+
+    /// Gibt zurück wie viele mehrfach abgerufene Unterrichtsstunden beim Hinzufügen zusammengeführt wurden
+    pub fn folded_duplicates(&self) -> usize {
+        self.folded_duplicates
+    }
+
+    /// Gibt die Version der Stammdaten zurück, die zum Zeitpunkt des Snapshots galten
+    pub fn master_data_version(&self) -> Option<&str> {
+        self.master_data_version.as_deref()
+    }
+
+    /// Setzt die Version der Stammdaten, die zum Zeitpunkt des Snapshots galten
+    pub fn set_master_data_version(&mut self, version: Option<String>) {
+        self.master_data_version = version;
+    }
+
+    /// Gibt die Id des Schlüssels zurück, mit dem die Lehrer pseudonymisiert wurden
+    pub fn pseudonym_key(&self) -> Option<&str> {
+        self.pseudonym_key.as_deref()
+    }
+
+    /// Setzt die Id des Schlüssels, mit dem die Lehrer pseudonymisiert wurden
+    pub fn set_pseudonym_key(&mut self, key_id: Option<String>) {
+        self.pseudonym_key = key_id;
+    }
+
+    /// Gibt den Zeitraum zurück, in dem die Stundenpläne abgerufen wurden
+    ///
+    /// # Returns
+    /// * `None` - Wenn der Snapshot aus dem alten Format überführt wurde
+    pub fn capture_times(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        self.capture_start.zip(self.capture_end)
+    }
+
+    /// Gibt zurück wie lange der Abruf der Stundenpläne gedauert hat, also wie weit die Unterrichtsstunden
+    /// des Snapshots zeitlich auseinander liegen können
+    pub fn capture_duration(&self) -> Option<chrono::Duration> {
+        self.capture_times().map(|(start, end)| end - start)
+    }
+
+    /// Setzt den Zeitraum, in dem die Stundenpläne abgerufen wurden
+    ///
+    /// # Arguments
+    /// * `start` - Zeitpunkt zu dem der erste Stundenplan abgerufen wurde
+    /// * `end` - Zeitpunkt zu dem der letzte Stundenplan empfangen wurde
+    pub fn set_capture_times(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) {
+        self.capture_start = Some(start);
+        self.capture_end = Some(end);
+    }
+
+    /// Gibt die Metadaten des Durchlaufs zurück, in dem der Snapshot erstellt wurde
+    pub fn run_metadata(&self) -> Option<&RunMetadata> {
+        self.run.as_ref()
+    }
+
+    /// Setzt die Metadaten des Durchlaufs, in dem der Snapshot erstellt wurde
+    pub fn set_run_metadata(&mut self, run: RunMetadata) {
+        self.run = Some(run);
+    }
+
+    /// Gibt zurück ob alle Stundenpläne abgerufen wurden. Unvollständige Snapshots sollten nicht in Statistiken einfließen,
+    /// da fehlende Elemente sonst als Elemente ohne Unterricht gezählt werden.
+    ///
+    /// # Returns
+    /// * `None` - Wenn der Snapshot aus dem alten Format überführt wurde und die Vollständigkeit unbekannt ist
+    pub fn is_complete(&self) -> Option<bool> {
+        self.run.as_ref().map(RunMetadata::is_complete)
+    }
+
+    /// Gibt einen Iterator über die Unterrichtsstunden des Snapshots zurück
+    pub fn iter(&self) -> std::slice::Iter<'_, Lesson> {
+        self.lessons.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = &'a Lesson;
+    type IntoIter = std::slice::Iter<'a, Lesson>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lessons.iter()
+    }
+}
+
+
+impl ArchivedSnapshot {
+    /// Gibt den Zeitpunkt des Snapshots zurück
+    pub fn datetime(&self) -> DateTime<Utc> {
+        read_archived(&self.datetime)
+    }
+
+    /// Gibt den Zeitraum zurück, dessen Stundenplan abgerufen wurde
+    pub fn window(&self) -> (NaiveDate, NaiveDate) {
+        (read_archived(&self.window_start), read_archived(&self.window_end))
+    }
+
+    /// Gibt zurück ob der Snapshot live erfasst oder nachträglich abgerufen wurde
+    pub fn kind(&self) -> SnapshotKind {
+        (&self.kind).into()
+    }
+
+    /// Gibt die archivierten Unterrichtsstunden zurück, ohne sie zu kopieren
+    pub fn lessons(&self) -> &[ArchivedLesson] {
+        self.lessons.as_slice()
+    }
+
+    /// Gibt zurück wie viele mehrfach abgerufene Unterrichtsstunden beim Hinzufügen zusammengeführt wurden
+    pub fn folded_duplicates(&self) -> usize {
+        read_archived(&self.folded_duplicates)
+    }
+
+    /// Gibt die Version der Stammdaten zurück, die zum Zeitpunkt des Snapshots galten
+    pub fn master_data_version(&self) -> Option<&str> {
+        self.master_data_version.as_ref().map(|version| version.as_str())
+    }
+
+    /// Gibt die Id des Schlüssels zurück, mit dem die Lehrer pseudonymisiert wurden
+    pub fn pseudonym_key(&self) -> Option<&str> {
+        self.pseudonym_key.as_ref().map(|key_id| key_id.as_str())
+    }
+
+    /// Gibt den Zeitraum zurück, in dem die Stundenpläne abgerufen wurden
+    pub fn capture_times(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let start = read_archived(self.capture_start.as_ref()?);
+        let end = read_archived(self.capture_end.as_ref()?);
+        Some((start, end))
+    }
+
+    /// Gibt die archivierten Metadaten des Durchlaufs zurück
+    pub fn run_metadata(&self) -> Option<&ArchivedRunMetadata> {
+        self.run.as_ref()
+    }
+
+    /// Gibt zurück ob alle Stundenpläne abgerufen wurden, `None` wenn die Vollständigkeit unbekannt ist
+    pub fn is_complete(&self) -> Option<bool> {
+        self.run.as_ref().map(ArchivedRunMetadata::is_complete)
+    }
+}
+
+
+/// Deserialisiert einen archivierten Wert ohne Heap Speicher, z.B. ein Datum oder eine Zahl
+///
+/// # Arguments
+/// * `archived` - Archivierter Wert, der bereits beim Öffnen der Datei validiert wurde
+fn read_archived<T, A>(archived: &A) -> T
+where
+    A: Deserialize<T, rkyv::Infallible>,
+{
+    // Der Fehlertyp von `rkyv::Infallible` hat keine Werte, die Deserialisierung kann daher nicht fehlschlagen
+    archived.deserialize(&mut rkyv::Infallible).expect("Deserialisieren mit rkyv::Infallible schlägt nie fehl")
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,PartialEq,Eq,serde::Serialize)]
+#[archive(check_bytes)]
+/// 'RunMetadata' beschreibt den Durchlauf, in dem ein Snapshot erstellt wurde
+pub struct RunMetadata {
+    /// Anzahl der Elemente, deren Stundenplan abgerufen werden sollte
+    pub attempted: usize,
+    /// Anzahl der Elemente, deren Stundenplan abgerufen wurde
+    pub succeeded: usize,
+    /// Fehlgeschlagene Abrufe von Stundenplänen und Elementlisten
+    pub failures: Vec<FetchFailure>,
+    /// Dauer des Abrufs in Millisekunden
+    pub duration_ms: u64,
+    /// Version des Scrapers, der den Snapshot erstellt hat
+    pub scraper_version: String,
+    /// Name des Rechners, auf dem der Scraper lief
+    pub host: String,
+    /// Gibt an ob der Durchlauf auf einem Failover Server lief (`STATE_CHECK_URL` gesetzt)
+    pub failover: bool,
+}
+
+impl RunMetadata {
+    /// Gibt zurück ob alle Stundenpläne abgerufen wurden
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl ArchivedRunMetadata {
+    /// Gibt zurück ob alle Stundenpläne abgerufen wurden
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,PartialEq,Eq,serde::Serialize)]
+#[archive(check_bytes)]
+/// 'FetchFailure' ist ein fehlgeschlagener Abruf während eines Durchlaufs
+pub struct FetchFailure {
+    /// Elementtyp des Elements (z.B. "class")
+    pub element_type: String,
+    /// Id des Elements in Untis. `None` wenn die Elemente des Typs nicht geladen werden konnten und bei Lehrern,
+    /// damit keine Untis Ids von Lehrern gespeichert werden
+    pub element_id: Option<usize>,
+    /// Art des Fehlers
+    pub error: FetchErrorKind,
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Hash,serde::Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug,Clone,Copy,PartialEq,Eq,Hash))]
+/// 'FetchErrorKind' ist die Art des Fehlers eines fehlgeschlagenen Abrufs
+pub enum FetchErrorKind {
+    /// Die Sitzung ist abgelaufen und der erneute Login ist fehlgeschlagen
+    SessionExpired,
+    /// Untis hat nicht rechtzeitig geantwortet
+    Timeout,
+    /// Der Abruf ist nicht möglich, z.B. wegen fehlender Berechtigung
+    Permanent,
+    /// Vorübergehender Fehler, der auch nach allen Wiederholungen bestand
+    Transient,
+}
+
+impl From<ErrorKind> for FetchErrorKind {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::SessionExpired => FetchErrorKind::SessionExpired,
+            ErrorKind::Timeout => FetchErrorKind::Timeout,
+            ErrorKind::Permanent => FetchErrorKind::Permanent,
+            ErrorKind::Transient => FetchErrorKind::Transient,
+        }
     }
 }
 
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Hash,serde::Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug,Clone,Copy,PartialEq,Eq,Hash))]
+/// 'SnapshotKind' gibt an wie ein Snapshot erstellt wurde
+pub enum SnapshotKind{
+    /// Der Snapshot wurde beim regulären Durchlauf erfasst und zeigt den Stundenplan zum Zeitpunkt des Snapshots
+    Live,
+    /// Der Snapshot wurde nachträglich für vergangene Tage abgerufen (`backfill`) und zeigt nur den endgültigen Stand
+    Backfill
+}
+
+impl From<&ArchivedSnapshotKind> for SnapshotKind {
+    fn from(value: &ArchivedSnapshotKind) -> Self {
+        match value {
+            ArchivedSnapshotKind::Live => SnapshotKind::Live,
+            ArchivedSnapshotKind::Backfill => SnapshotKind::Backfill,
+        }
+    }
+}
 
-#[derive(Archive,Serialize,Deserialize,Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// 'LessonKey' identifiziert eine Unterrichtsstunde innerhalb eines Snapshots und über mehrere Snapshots hinweg
+pub struct LessonKey {
+    /// Datum der Unterrichtsstunde
+    pub date: NaiveDate,
+    /// Beginn der Unterrichtsstunde
+    pub start_time: NaiveTime,
+    /// Id der Unterrichtsstunde in Untis
+    pub id: usize,
+}
+
+impl From<&Lesson> for LessonKey {
+    fn from(lesson: &Lesson) -> Self {
+        Self { date: lesson.date, start_time: lesson.start_time, id: lesson.id }
+    }
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,PartialEq,Eq,serde::Serialize)]
+#[archive(check_bytes)]
 /// 'Lesson' repräsentiert eine Unterrichtsstunde, die auf dem Stundenplan hinterlegt ist.
 /// 
 pub struct Lesson {
+    /// Id der Unterrichtsstunde in Untis
+    pub id: usize,
+    /// Datum an dem die Unterrichtsstunde stattfindet
+    pub date: NaiveDate,
+    /// Beginn der Unterrichtsstunde
+    pub start_time: NaiveTime,
+    /// Ende der Unterrichtsstunde
+    pub end_time: NaiveTime,
     /// Klassen die an der Unterrichtsstunde teilnehmen
     pub classes: Vec<String>,
     /// Lehrer die die Unterrichtsstunde halten
@@ -129,13 +539,77 @@ pub struct Lesson {
     pub lesson_code: LessonCode,
     /// Beschreibung der Unterrichtsstunde
     pub description: String,
-    /// Thema der Unterrichtsstunde
-    pub topic: String,
+    /// Fächer der Unterrichtsstunde, leer wenn in Untis kein Fach hinterlegt ist.
+    /// `None` wenn die Fächer nicht erfasst wurden, z.B. bei Unterrichtsstunden aus dem alten Format, die als Thema "None" gespeichert haben.
+    pub subjects: Option<Vec<Subject>>,
     /// Vertretingshinweis der Unterrichtsstunde
     pub sub_text: Option<String>,
+    /// Vertretene Lehrer, ursprünglich eingeplanter und vertretender Lehrer
+    pub teacher_substitutions: Vec<Substitution>,
+    /// Verlegte Räume, ursprünglich eingeplanter und neuer Raum
+    pub room_substitutions: Vec<Substitution>,
+}
+
+impl Lesson {
+    /// Gibt zurück ob die Unterrichtsstunde über ihre Identität (Id, Datum und Beginn) zugeordnet werden kann.
+    /// Unterrichtsstunden aus dem alten Format haben weder Id noch Uhrzeit und teilen sich alle dieselbe Identität.
+    pub fn has_identity(&self) -> bool {
+        !(self.id == 0 && self.start_time == migrate::UNKNOWN_TIME && self.end_time == migrate::UNKNOWN_TIME)
+    }
+
+    /// Gibt das Thema der Unterrichtsstunde zurück, den Kurznamen des ersten Fachs
+    ///
+    /// # Returns
+    /// * `None` - Wenn kein Fach hinterlegt ist oder die Fächer nicht erfasst wurden
+    pub fn topic(&self) -> Option<&str> {
+        self.subjects.as_deref()?.first().map(|subject| subject.name.as_str())
+    }
+}
+
+impl ArchivedLesson {
+    /// Gibt das Thema der archivierten Unterrichtsstunde zurück, den Kurznamen des ersten Fachs
+    pub fn topic(&self) -> Option<&str> {
+        self.subjects.as_ref()?.first().map(|subject| subject.name.as_str())
+    }
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,PartialEq,Eq,serde::Serialize)]
+#[archive(check_bytes)]
+/// 'Substitution' repräsentiert einen Lehrer oder Raum, der bei einer unregelmäßigen Unterrichtsstunde ersetzt wurde
+pub struct Substitution {
+    /// Ursprünglich eingeplanter Lehrer bzw. Raum
+    pub original: String,
+    /// Lehrer bzw. Raum der stattdessen eingetragen ist
+    pub replacement: String,
 }
 
-#[derive(Archive,Serialize,Deserialize,Debug)]
+impl Substitution {
+    /// Gibt die Ersetzungen einer Liste von Untis Elementen zurück. Untis gibt bei ersetzten Elementen
+    /// das ursprüngliche Element als `orgname` an.
+    fn from_items(items: &[untis::IdItem]) -> Vec<Self> {
+        items
+            .iter()
+            .filter_map(|item| {
+                let original = item.orgname.as_ref()?;
+                Some(Self { original: original.to_string(), replacement: item.name.to_string() })
+            })
+            .collect()
+    }
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,PartialEq,Eq,serde::Serialize)]
+#[archive(check_bytes)]
+/// 'Subject' repräsentiert ein Fach einer Unterrichtsstunde
+pub struct Subject {
+    /// Kurzname des Fachs (z.B. "M")
+    pub name: String,
+    /// Langname des Fachs (z.B. "Mathematik"), fehlt bei Daten aus dem alten Format
+    pub long_name: Option<String>,
+}
+
+#[derive(Archive,Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,Hash,serde::Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug,Clone,Copy,PartialEq,Eq,Hash))]
 /// 'LessonCode' repräsentiert die Art der Unterrichtsstunde
 pub enum LessonCode{
     /// Reguläre Unterrichtsstunde
@@ -146,12 +620,25 @@ pub enum LessonCode{
     Cancelled
 }
 
+impl From<&ArchivedLessonCode> for LessonCode {
+    fn from(value: &ArchivedLessonCode) -> Self {
+        match value {
+            ArchivedLessonCode::Regular => LessonCode::Regular,
+            ArchivedLessonCode::Irregular => LessonCode::Irregular,
+            ArchivedLessonCode::Cancelled => LessonCode::Cancelled,
+        }
+    }
+}
+
 impl From<&untis::Lesson> for Lesson{
     fn from(value: &untis::Lesson) -> Self {
 
-        // Erstellt einen String mit dem Wert 'None' als Standardwert für das Thema der Unterrichtsstunde
-        let none = "None".to_string();
         Lesson { 
+            // Übernimmt die Id, das Datum und die Uhrzeit der Unterrichtsstunde
+            id: value.id,
+            date: value.date.0,
+            start_time: value.start_time.0,
+            end_time: value.end_time.0,
             // Konvertiert die Klassen, Lehrer und Räume in einen String Vector
             classes: value.classes.iter().map(|class|class.name.to_string()).collect(), 
             teachers: value.teachers.iter().map(|teacher| teacher.name.to_string()).collect(), 
@@ -164,10 +651,61 @@ impl From<&untis::Lesson> for Lesson{
                 untis::LessonCode::Cancelled => LessonCode::Cancelled,
             },
             description: value.lstext.to_owned(), 
-            // Konvertiert das Thema der Unterrichtsstunde in einen String, wenn es vorhanden ist. Ansonsten wird der Standardwert 'None' zurückgegeben
-            // Sollte mehr als ein Thema vorhanden sein, wird nur das erste Thema zurückgegeben
-            topic: value.subjects.iter().take(1).map(|subject|subject.name.clone()).collect::<Vec<String>>().get(0).unwrap_or(&none).to_owned(), 
-            sub_text: value.subst_text.to_owned()
+            // Übernimmt alle Fächer der Unterrichtsstunde mit Kurz- und Langname
+            subjects: Some(value.subjects.iter().map(|subject| Subject {
+                name: subject.name.to_string(),
+                long_name: Some(subject.long_name.to_string()).filter(|long_name| !long_name.is_empty()),
+            }).collect()), 
+            sub_text: value.subst_text.to_owned(),
+            // Übernimmt die ursprünglich eingeplanten Lehrer und Räume, wenn diese ersetzt wurden
+            teacher_substitutions: Substitution::from_items(&value.teachers),
+            room_substitutions: Substitution::from_items(&value.rooms),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{lesson, test_date};
+
+    #[test]
+    fn duplicate_lessons_are_folded() {
+        let mut snapshot = Snapshot::new(test_date(), test_date());
+        snapshot.add_lesson(lesson(1, "10a", 8));
+        snapshot.add_lesson(lesson(1, "10b", 8));
+        snapshot.add_lesson(lesson(1, "10a", 8));
+        snapshot.add_lesson(lesson(2, "10a", 8));
+
+        assert_eq!(snapshot.lessons().len(), 2);
+        assert_eq!(snapshot.lessons()[0].classes, ["10a", "10b"]);
+        assert_eq!(snapshot.lessons()[1], lesson(2, "10a", 8));
+        assert_eq!(snapshot.folded_duplicates(), 2);
+    }
+
+    #[test]
+    fn lessons_at_other_times_are_kept() {
+        let mut snapshot = Snapshot::new(test_date(), test_date());
+        let mut next_day = lesson(1, "10a", 8);
+        next_day.date = test_date().succ_opt().unwrap();
+        snapshot.add_lesson(lesson(1, "10a", 8));
+        snapshot.add_lesson(lesson(1, "10a", 9));
+        snapshot.add_lesson(next_day);
+
+        assert_eq!(snapshot.lessons().len(), 3);
+        assert_eq!(snapshot.folded_duplicates(), 0);
+    }
+
+    #[test]
+    fn duplicates_are_folded_after_deserializing() {
+        let mut snapshot = Snapshot::new(test_date(), test_date());
+        snapshot.add_lesson(lesson(1, "10a", 8));
+        let bytes = rkyv::to_bytes::<_, 1024>(&snapshot).unwrap();
+        let mut snapshot = rkyv::from_bytes::<Snapshot>(&bytes).unwrap();
+
+        snapshot.add_lesson(lesson(1, "10b", 8));
+        assert_eq!(snapshot.lessons().len(), 1);
+        assert_eq!(snapshot.lessons()[0].classes, ["10a", "10b"]);
+        assert_eq!(snapshot.folded_duplicates(), 1);
+    }
+}
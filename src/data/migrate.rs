@@ -0,0 +1,145 @@
+//! Das alte Datenformat und seine Überführung in die aktuelle Version.
+//!
+//! In Version 0 wurde das gesamte `ExportFile` ohne Dateikopf am Stück gespeichert. Die Unterrichtsstunden enthalten weder
+//! Id noch Datum oder Uhrzeit. Beim Überführen erhalten sie die Id `0`, das Datum des Snapshots und [`UNKNOWN_TIME`] als
+//! Beginn und Ende, sie können daher nicht über mehrere Snapshots hinweg zugeordnet werden. Das Thema wurde als einzelner
+//! String gespeichert ("None" wenn kein Fach hinterlegt war) und Lehrer wurden ohne gespeicherten Schlüssel als
+//! `Sha256(SECRET || Name)` pseudonymisiert.
+//!
+//! Die Snapshot Logs beginnen mit Version 6, die Versionen 1 bis 5 wurden nie veröffentlicht und werden nicht gelesen.
+//!
+//! Die Strukturen in diesem Modul dürfen nicht verändert werden, da sie das Format bereits gespeicherter Dateien beschreiben.
+//! Sie verwenden keine aktuellen Strukturen außer [`LessonCode`], dessen Format daher ebenfalls nicht verändert werden darf.
+//! Ändert es sich, muss es vorher hier eingefroren werden.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use rkyv::{Archive, Deserialize, Serialize};
+
+use super::{Lesson, LessonCode, Snapshot, Subject};
+use crate::pseudonym::LEGACY_KEY_ID;
+
+/// Thema, das Version 0 gespeichert hat, wenn kein Fach hinterlegt war
+const NO_TOPIC: &str = "None";
+/// Platzhalter für Beginn und Ende der Unterrichtsstunden aus Version 0, in der keine Uhrzeit gespeichert wurde
+pub const UNKNOWN_TIME: NaiveTime = NaiveTime::MIN;
+
+#[derive(Archive, Serialize, Deserialize, Debug)]
+#[archive(check_bytes)]
+/// 'ExportFile' in Version 0, die gesamte Tagesdatei am Stück
+pub struct ExportFileV0 {
+    /// Datum der Exportieren Daten
+    pub date: DateTime<Utc>,
+    /// Snapshots des Stundenplans in der Exportieren Datei
+    pub snapshots: Vec<SnapshotV0>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug)]
+#[archive(check_bytes)]
+/// 'Snapshot' in Version 0
+pub struct SnapshotV0 {
+    /// Datum mit Zeitpunkt des jeweiligen Snapshots
+    pub datetime: DateTime<Utc>,
+    /// Unterrichtstunden die zum Zeitpunkt des Snapshots auf den Stundenplan hinterlegt waren
+    pub lessons: Vec<LessonV0>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug)]
+#[archive(check_bytes)]
+/// 'Lesson' in Version 0, ohne Id, Datum und Uhrzeit
+pub struct LessonV0 {
+    /// Klassen die an der Unterrichtsstunde teilnehmen
+    pub classes: Vec<String>,
+    /// Lehrer die die Unterrichtsstunde halten
+    pub teachers: Vec<String>,
+    /// Räume in denen die Unterrichtsstunde stattfindet
+    pub rooms: Vec<String>,
+    /// Art der Unterrichtsstunde
+    pub lesson_code: LessonCode,
+    /// Beschreibung der Unterrichtsstunde
+    pub description: String,
+    /// Kurzname des ersten Fachs oder "None"
+    pub topic: String,
+    /// Vertretingshinweis der Unterrichtsstunde
+    pub sub_text: Option<String>,
+}
+
+impl LessonV0 {
+    /// Überführt eine Unterrichtsstunde in die aktuelle Version. Id und Uhrzeit sind unbekannt, ursprünglich eingeplante
+    /// Lehrer und Räume wurden nicht gespeichert.
+    ///
+    /// # Arguments
+    /// * `date` - Tag des Snapshots, Version 0 hat nur den Stundenplan dieses Tages abgerufen
+    fn into_lesson(self, date: NaiveDate) -> Lesson {
+        // Version 0 hat nur den Kurznamen des ersten Fachs gespeichert. "None" lässt sich nicht sicher von einem Fach
+        // mit diesem Namen unterscheiden, die Fächer gelten daher als nicht erfasst.
+        let subjects = match self.topic.as_str() {
+            NO_TOPIC => None,
+            _ => Some(vec![Subject { name: self.topic, long_name: None }]),
+        };
+        Lesson {
+            id: 0,
+            date,
+            start_time: UNKNOWN_TIME,
+            end_time: UNKNOWN_TIME,
+            classes: self.classes,
+            teachers: self.teachers,
+            rooms: self.rooms,
+            lesson_code: self.lesson_code,
+            description: self.description,
+            subjects,
+            sub_text: self.sub_text,
+            teacher_substitutions: Vec::new(),
+            room_substitutions: Vec::new(),
+        }
+    }
+}
+
+impl From<SnapshotV0> for Snapshot {
+    fn from(value: SnapshotV0) -> Self {
+        // Version 0 hat nur den Stundenplan des Tages abgerufen, an dem der Snapshot erstellt wurde
+        let date = value.datetime.with_timezone(&chrono::Local).date_naive();
+        let mut snapshot = Self::new(date, date);
+        snapshot.datetime = value.datetime;
+        snapshot.lessons = value.lessons.into_iter().map(|lesson| lesson.into_lesson(date)).collect();
+        // Version 0 hat alle Lehrer mit dem alten Verfahren ohne HMAC pseudonymisiert
+        snapshot.pseudonym_key = Some(LEGACY_KEY_ID.to_string());
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::UNKNOWN_TIME;
+    use crate::{
+        data::{ExportFile, LessonCode},
+        pseudonym::LEGACY_KEY_ID,
+        testing::{baseline_day_file, temp_dir},
+    };
+
+    #[test]
+    fn reads_baseline_day_file() {
+        let path = temp_dir("baseline").join("20.bin");
+        std::fs::write(&path, baseline_day_file()).unwrap();
+
+        let export_file = ExportFile::read(&path).unwrap();
+        assert_eq!(export_file.snapshots().len(), 1);
+        let snapshot = &export_file.snapshots()[0];
+        let date = NaiveDate::from_ymd_opt(2023, 11, 20).unwrap();
+        assert_eq!(snapshot.window(), (date, date));
+        assert_eq!(snapshot.pseudonym_key(), Some(LEGACY_KEY_ID));
+        assert_eq!(snapshot.is_complete(), None);
+
+        let lessons = snapshot.lessons();
+        assert_eq!(lessons.len(), 2);
+        assert_eq!((lessons[0].id, lessons[0].date, lessons[0].start_time, lessons[0].end_time), (0, date, UNKNOWN_TIME, UNKNOWN_TIME));
+        assert_eq!(lessons[0].classes, ["10a"]);
+        assert_eq!(lessons[0].teachers, ["4f2a"]);
+        assert_eq!(lessons[0].topic(), Some("M"));
+        assert_eq!(lessons[1].lesson_code, LessonCode::Cancelled);
+        assert_eq!(lessons[1].topic(), None);
+        assert_eq!(lessons[1].subjects, None);
+        assert_eq!(lessons[1].sub_text.as_deref(), Some("Entfall"));
+    }
+}
@@ -0,0 +1,288 @@
+//! Vergleich zweier Snapshots.
+//!
+//! Unterrichtsstunden werden über ihre Identität (Untis Id, Datum und Beginn) einander zugeordnet. Daraus ergibt sich eine Liste
+//! von Änderungen, z.B. neu eingetragene Vertretungen, Entfälle oder getauschte Lehrer und Räume.
+//! Snapshots aus dem alten Format können nicht verglichen werden, da ihre Unterrichtsstunden keine Identität haben.
+//! Wurden die Lehrer der beiden Snapshots mit unterschiedlichen Schlüsseln pseudonymisiert, werden sie nicht verglichen.
+
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Utc};
+
+pub use crate::data::LessonKey;
+use crate::data::{Lesson, LessonCode, Snapshot};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 'Change' repräsentiert eine Änderung einer Unterrichtsstunde zwischen zwei Snapshots
+pub enum Change<'a> {
+    /// Die Unterrichtsstunde ist nur im neueren Snapshot vorhanden
+    Added(&'a Lesson),
+    /// Die Unterrichtsstunde ist nur im älteren Snapshot vorhanden
+    Removed(&'a Lesson),
+    /// Die Art der Unterrichtsstunde hat sich geändert (z.B. Regulär → Ausgefallen)
+    CodeChanged {
+        /// Unterrichtsstunde im neueren Snapshot
+        lesson: &'a Lesson,
+        /// Art im älteren Snapshot
+        from: LessonCode,
+        /// Art im neueren Snapshot
+        to: LessonCode,
+    },
+    /// Die Lehrer der Unterrichtsstunde haben sich geändert
+    TeachersChanged {
+        /// Unterrichtsstunde im neueren Snapshot
+        lesson: &'a Lesson,
+        /// Lehrer im älteren Snapshot
+        from: &'a [String],
+        /// Lehrer im neueren Snapshot
+        to: &'a [String],
+    },
+    /// Die Räume der Unterrichtsstunde haben sich geändert
+    RoomsChanged {
+        /// Unterrichtsstunde im neueren Snapshot
+        lesson: &'a Lesson,
+        /// Räume im älteren Snapshot
+        from: &'a [String],
+        /// Räume im neueren Snapshot
+        to: &'a [String],
+    },
+}
+
+impl<'a> Change<'a> {
+    /// Gibt die Unterrichtsstunde zurück, auf die sich die Änderung bezieht
+    pub fn lesson(&self) -> &'a Lesson {
+        match self {
+            Change::Added(lesson) | Change::Removed(lesson) => *lesson,
+            Change::CodeChanged { lesson, .. } | Change::TeachersChanged { lesson, .. } | Change::RoomsChanged { lesson, .. } => *lesson,
+        }
+    }
+}
+
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lesson = self.lesson();
+        write!(
+            f,
+            "{} {}-{} {} ({}): ",
+            lesson.date,
+            lesson.start_time.format("%H:%M"),
+            lesson.end_time.format("%H:%M"),
+            lesson.topic().unwrap_or("-"),
+            lesson.classes.join(", ")
+        )?;
+        match self {
+            Change::Added(_) => write!(f, "hinzugefügt ({:?})", lesson.lesson_code),
+            Change::Removed(_) => write!(f, "entfernt"),
+            Change::CodeChanged { from, to, .. } => write!(f, "{:?} -> {:?}", from, to),
+            Change::TeachersChanged { from, to, .. } => write!(f, "Lehrer [{}] -> [{}]", from.join(", "), to.join(", ")),
+            Change::RoomsChanged { from, to, .. } => write!(f, "Räume [{}] -> [{}]", from.join(", "), to.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// 'SnapshotDiff' enthält alle Änderungen zwischen zwei Snapshots
+pub struct SnapshotDiff<'a> {
+    /// Zeitpunkt des älteren Snapshots
+    pub old: DateTime<Utc>,
+    /// Zeitpunkt des neueren Snapshots
+    pub new: DateTime<Utc>,
+    /// Gibt an ob die Lehrer verglichen wurden. Sie werden nur verglichen, wenn beide Snapshots mit demselben Schlüssel
+    /// pseudonymisiert wurden, da sich sonst alle Pseudonyme unterscheiden.
+    pub teachers_compared: bool,
+    /// Änderungen, sortiert nach Datum und Beginn der Unterrichtsstunden
+    pub changes: Vec<Change<'a>>,
+}
+
+impl SnapshotDiff<'_> {
+    /// Gibt an ob sich zwischen den Snapshots nichts geändert hat
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Zählt die Änderungen, bei denen die Art der Unterrichtsstunde von `from` nach `to` gewechselt ist
+    pub fn count_transitions(&self, from: LessonCode, to: LessonCode) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, Change::CodeChanged { from: old, to: new, .. } if *old == from && *new == to))
+            .count()
+    }
+}
+
+impl fmt::Display for SnapshotDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} -> {}: {} Änderungen", self.old, self.new, self.changes.len())?;
+        if !self.teachers_compared {
+            writeln!(f, "  Lehrer wurden nicht verglichen, da die Snapshots mit unterschiedlichen Schlüsseln pseudonymisiert wurden")?;
+        }
+        for change in &self.changes {
+            let marker = match change {
+                Change::Added(_) => '+',
+                Change::Removed(_) => '-',
+                _ => '~',
+            };
+            writeln!(f, "  {} {}", marker, change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Vergleicht zwei Listen unabhängig von ihrer Reihenfolge
+fn same_elements(a: &[String], b: &[String]) -> bool {
+    let mut a: Vec<&String> = a.iter().collect();
+    let mut b: Vec<&String> = b.iter().collect();
+    a.sort();
+    b.sort();
+    a == b
+}
+
+/// Vergleicht zwei Snapshots und gibt die Änderungen zurück
+///
+/// # Arguments
+/// * `old` - Älterer Snapshot
+/// * `new` - Neuerer Snapshot
+///
+/// # Returns
+/// * `SnapshotDiff` - Änderungen zwischen den Snapshots
+/// * `Err` - Wenn ein Snapshot Unterrichtsstunden ohne Identität aus dem alten Format enthält
+pub fn diff<'a>(old: &'a Snapshot, new: &'a Snapshot) -> Result<SnapshotDiff<'a>> {
+    for snapshot in [old, new] {
+        if !snapshot.lessons().iter().all(Lesson::has_identity) {
+            anyhow::bail!(
+                "Der Snapshot vom {} stammt aus dem alten Format, seine Unterrichtsstunden haben weder Id noch Uhrzeit und können nicht zugeordnet werden",
+                snapshot.datetime()
+            );
+        }
+    }
+    let teachers_compared = old.pseudonym_key() == new.pseudonym_key();
+
+    // Ordnet die Unterrichtsstunden beider Snapshots ihrer Identität zu
+    let mut lessons: BTreeMap<LessonKey, (Option<&'a Lesson>, Option<&'a Lesson>)> = BTreeMap::new();
+    for lesson in old {
+        lessons.entry(lesson.into()).or_default().0 = Some(lesson);
+    }
+    for lesson in new {
+        lessons.entry(lesson.into()).or_default().1 = Some(lesson);
+    }
+
+    let mut changes = Vec::new();
+    for (old_lesson, new_lesson) in lessons.into_values() {
+        match (old_lesson, new_lesson) {
+            (None, Some(lesson)) => changes.push(Change::Added(lesson)),
+            (Some(lesson), None) => changes.push(Change::Removed(lesson)),
+            (Some(old_lesson), Some(lesson)) => {
+                if old_lesson.lesson_code != lesson.lesson_code {
+                    changes.push(Change::CodeChanged { lesson, from: old_lesson.lesson_code, to: lesson.lesson_code });
+                }
+                if teachers_compared && !same_elements(&old_lesson.teachers, &lesson.teachers) {
+                    changes.push(Change::TeachersChanged { lesson, from: &old_lesson.teachers, to: &lesson.teachers });
+                }
+                if !same_elements(&old_lesson.rooms, &lesson.rooms) {
+                    changes.push(Change::RoomsChanged { lesson, from: &old_lesson.rooms, to: &lesson.rooms });
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    Ok(SnapshotDiff { old: old.datetime(), new: new.datetime(), teachers_compared, changes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{baseline_snapshot, lesson, test_date};
+
+    /// Erstellt einen Snapshot aus den angegebenen Unterrichtsstunden
+    fn snapshot(lessons: Vec<Lesson>) -> Snapshot {
+        let mut snapshot = Snapshot::new(test_date(), test_date());
+        for lesson in lessons {
+            snapshot.add_lesson(lesson);
+        }
+        snapshot
+    }
+
+    #[test]
+    fn identical_snapshots_have_no_changes() {
+        let old = snapshot(vec![lesson(1, "10a", 8), lesson(2, "10b", 9)]);
+        let new = snapshot(vec![lesson(2, "10b", 9), lesson(1, "10a", 8)]);
+        assert!(diff(&old, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_lessons() {
+        let old = snapshot(vec![lesson(1, "10a", 8), lesson(2, "10b", 9)]);
+        let new = snapshot(vec![lesson(1, "10a", 8), lesson(3, "10c", 10)]);
+        let changes = diff(&old, &new).unwrap().changes;
+        assert_eq!(changes, [Change::Removed(&old.lessons()[1]), Change::Added(&new.lessons()[1])]);
+    }
+
+    #[test]
+    fn moved_lesson_is_removed_and_added() {
+        // Eine verschobene Unterrichtsstunde hat einen anderen Beginn und damit eine andere Identität
+        let old = snapshot(vec![lesson(1, "10a", 8)]);
+        let new = snapshot(vec![lesson(1, "10a", 10)]);
+        let changes = diff(&old, &new).unwrap().changes;
+        assert_eq!(changes, [Change::Removed(&old.lessons()[0]), Change::Added(&new.lessons()[0])]);
+    }
+
+    #[test]
+    fn changed_lessons() {
+        let mut cancelled = lesson(1, "10a", 8);
+        cancelled.lesson_code = LessonCode::Cancelled;
+        let mut substituted = lesson(2, "10b", 9);
+        substituted.lesson_code = LessonCode::Irregular;
+        substituted.teachers = vec!["9c1d".to_string()];
+        substituted.rooms = vec!["R202".to_string()];
+        let old = snapshot(vec![lesson(1, "10a", 8), lesson(2, "10b", 9)]);
+        let new = snapshot(vec![cancelled, substituted]);
+
+        let diff = diff(&old, &new).unwrap();
+        let (cancelled, substituted) = (&new.lessons()[0], &new.lessons()[1]);
+        assert_eq!(
+            diff.changes,
+            [
+                Change::CodeChanged { lesson: cancelled, from: LessonCode::Regular, to: LessonCode::Cancelled },
+                Change::CodeChanged { lesson: substituted, from: LessonCode::Regular, to: LessonCode::Irregular },
+                Change::TeachersChanged { lesson: substituted, from: &old.lessons()[1].teachers, to: &substituted.teachers },
+                Change::RoomsChanged { lesson: substituted, from: &old.lessons()[1].rooms, to: &substituted.rooms },
+            ]
+        );
+        assert_eq!(diff.count_transitions(LessonCode::Regular, LessonCode::Cancelled), 1);
+        assert_eq!(diff.count_transitions(LessonCode::Regular, LessonCode::Irregular), 1);
+    }
+
+    #[test]
+    fn order_of_teachers_is_ignored() {
+        let mut old_lesson = lesson(1, "10a", 8);
+        old_lesson.teachers = vec!["4f2a".to_string(), "9c1d".to_string()];
+        let mut new_lesson = old_lesson.clone();
+        new_lesson.teachers.reverse();
+        assert!(diff(&snapshot(vec![old_lesson]), &snapshot(vec![new_lesson])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn teachers_are_not_compared_across_keys() {
+        let mut substituted = lesson(1, "10a", 8);
+        substituted.teachers = vec!["9c1d".to_string()];
+        let mut old = snapshot(vec![lesson(1, "10a", 8)]);
+        old.set_pseudonym_key(Some("2023-08-01".to_string()));
+        let mut new = snapshot(vec![substituted]);
+        new.set_pseudonym_key(Some("2024-08-01".to_string()));
+
+        let diff = diff(&old, &new).unwrap();
+        assert!(diff.is_empty());
+        assert!(!diff.teachers_compared);
+    }
+
+    #[test]
+    fn migrated_snapshots_are_not_compared() {
+        // Alle Unterrichtsstunden aus dem alten Format haben dieselbe Identität und würden sonst zusammenfallen
+        let old = Snapshot::from(baseline_snapshot());
+        let new = Snapshot::from(baseline_snapshot());
+        assert!(diff(&old, &new).is_err());
+        assert!(diff(&old, &snapshot(vec![lesson(1, "10a", 8)])).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Export der Tagesdateien in Formate, die auch außerhalb von Rust gelesen werden können.
+//!
+//! Für CSV und JSON Lines wird `ExportFile` → `Snapshot` → `Lesson` zu einer Zeile pro Unterrichtsstunde und Snapshot abgeflacht.
+
+use std::{fmt, io::Write, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::data::{ExportFile, Lesson, LessonCode, Snapshot, SnapshotKind, Subject, Substitution};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Trennzeichen für Listen (Klassen, Lehrer, Räume, Fächer) innerhalb einer CSV Zelle
+pub const CSV_LIST_SEPARATOR: &str = ";";
+
+/// Trennzeichen zwischen ursprünglichem und ersetzendem Lehrer bzw. Raum innerhalb einer CSV Zelle
+pub const CSV_SUBSTITUTION_SEPARATOR: &str = ">";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'ExportFormat' repräsentiert die Formate in die exportiert werden kann
+pub enum ExportFormat {
+    /// Die gesamte Tagesdatei als JSON Dokument
+    Json,
+    /// Eine Zeile pro Unterrichtsstunde und Snapshot als CSV
+    Csv,
+    /// Eine Zeile pro Unterrichtsstunde und Snapshot als JSON Lines
+    Jsonl,
+    /// Nach Monaten partitionierter Parquet Datensatz
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// Nach Monaten partitionierter Arrow IPC Datensatz
+    #[cfg(feature = "parquet")]
+    Arrow,
+}
+
+impl ExportFormat {
+    /// Gibt das spaltenorientierte Format zurück, wenn in einen partitionierten Datensatz exportiert wird
+    #[cfg(feature = "parquet")]
+    pub fn columnar(&self) -> Option<crate::columnar::ColumnarFormat> {
+        match self {
+            ExportFormat::Parquet => Some(crate::columnar::ColumnarFormat::Parquet),
+            ExportFormat::Arrow => Some(crate::columnar::ColumnarFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "json-lines" => Ok(ExportFormat::Jsonl),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(ExportFormat::Parquet),
+            #[cfg(feature = "parquet")]
+            "arrow" | "ipc" => Ok(ExportFormat::Arrow),
+            _ => Err(format!("Unbekanntes Format \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Jsonl => write!(f, "jsonl"),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => write!(f, "parquet"),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Arrow => write!(f, "arrow"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// 'LessonRow' repräsentiert eine Unterrichtsstunde eines Snapshots als flache Zeile
+pub struct LessonRow<'a> {
+    /// Zeitpunkt des Snapshots
+    pub snapshot: DateTime<Utc>,
+    /// Art des Snapshots (live oder nachträglich abgerufen)
+    pub snapshot_kind: SnapshotKind,
+    /// Version der Stammdaten des Snapshots
+    pub master_data_version: Option<&'a str>,
+    /// Id des Schlüssels, mit dem die Lehrer des Snapshots pseudonymisiert wurden
+    pub pseudonym_key: Option<&'a str>,
+    /// Gibt an ob alle Stundenpläne des Snapshots abgerufen wurden, leer bei Snapshots aus dem alten Format
+    pub snapshot_complete: Option<bool>,
+    /// Datum der Unterrichtsstunde
+    pub date: NaiveDate,
+    /// Beginn der Unterrichtsstunde
+    pub start_time: NaiveTime,
+    /// Ende der Unterrichtsstunde
+    pub end_time: NaiveTime,
+    /// Id der Unterrichtsstunde in Untis
+    pub lesson_id: usize,
+    /// Klassen die an der Unterrichtsstunde teilnehmen
+    pub classes: &'a [String],
+    /// Pseudonymisierte Lehrer die die Unterrichtsstunde halten
+    pub teachers: &'a [String],
+    /// Räume in denen die Unterrichtsstunde stattfindet
+    pub rooms: &'a [String],
+    /// Art der Unterrichtsstunde
+    pub lesson_code: LessonCode,
+    /// Beschreibung der Unterrichtsstunde
+    pub description: &'a str,
+    /// Thema der Unterrichtsstunde (Kurzname des ersten Fachs)
+    pub topic: Option<&'a str>,
+    /// Fächer der Unterrichtsstunde, `None` wenn sie nicht erfasst wurden
+    pub subjects: Option<&'a [Subject]>,
+    /// Vertretungshinweis der Unterrichtsstunde
+    pub sub_text: Option<&'a str>,
+    /// Vertretene Lehrer der Unterrichtsstunde
+    pub teacher_substitutions: &'a [Substitution],
+    /// Verlegte Räume der Unterrichtsstunde
+    pub room_substitutions: &'a [Substitution],
+}
+
+impl<'a> LessonRow<'a> {
+    /// Spaltennamen der CSV Datei
+    pub const CSV_HEADER: [&'static str; 20] = [
+        "snapshot",
+        "snapshot_kind",
+        "master_data_version",
+        "pseudonym_key",
+        "snapshot_complete",
+        "date",
+        "start_time",
+        "end_time",
+        "lesson_id",
+        "classes",
+        "teachers",
+        "rooms",
+        "lesson_code",
+        "description",
+        "topic",
+        "subjects",
+        "subjects_long",
+        "sub_text",
+        "teacher_substitutions",
+        "room_substitutions",
+    ];
+
+    /// Erstellt eine Zeile aus einer Unterrichtsstunde eines Snapshots
+    pub fn new(snapshot: &'a Snapshot, lesson: &'a Lesson) -> Self {
+        Self {
+            snapshot: snapshot.datetime(),
+            snapshot_kind: snapshot.kind(),
+            master_data_version: snapshot.master_data_version(),
+            pseudonym_key: snapshot.pseudonym_key(),
+            snapshot_complete: snapshot.is_complete(),
+            date: lesson.date,
+            start_time: lesson.start_time,
+            end_time: lesson.end_time,
+            lesson_id: lesson.id,
+            classes: &lesson.classes,
+            teachers: &lesson.teachers,
+            rooms: &lesson.rooms,
+            lesson_code: lesson.lesson_code,
+            description: &lesson.description,
+            topic: lesson.topic(),
+            subjects: lesson.subjects.as_deref(),
+            sub_text: lesson.sub_text.as_deref(),
+            teacher_substitutions: &lesson.teacher_substitutions,
+            room_substitutions: &lesson.room_substitutions,
+        }
+    }
+
+    /// Gibt die Zeile als CSV Datensatz zurück. Listen werden mit `CSV_LIST_SEPARATOR` verbunden,
+    /// Ersetzungen als "original>ersatz" geschrieben.
+    pub fn csv_record(&self) -> [String; 20] {
+        [
+            self.snapshot.to_rfc3339_opts(SecondsFormat::Secs, true),
+            format!("{:?}", self.snapshot_kind),
+            self.master_data_version.unwrap_or_default().to_string(),
+            self.pseudonym_key.unwrap_or_default().to_string(),
+            self.snapshot_complete.map(|complete| complete.to_string()).unwrap_or_default(),
+            self.date.to_string(),
+            self.start_time.to_string(),
+            self.end_time.to_string(),
+            self.lesson_id.to_string(),
+            self.classes.join(CSV_LIST_SEPARATOR),
+            self.teachers.join(CSV_LIST_SEPARATOR),
+            self.rooms.join(CSV_LIST_SEPARATOR),
+            format!("{:?}", self.lesson_code),
+            self.description.to_string(),
+            self.topic.unwrap_or_default().to_string(),
+            self.subjects.unwrap_or_default().iter().map(|subject| subject.name.as_str()).collect::<Vec<_>>().join(CSV_LIST_SEPARATOR),
+            self.subjects
+                .unwrap_or_default()
+                .iter()
+                .map(|subject| subject.long_name.as_deref().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(CSV_LIST_SEPARATOR),
+            self.sub_text.unwrap_or_default().to_string(),
+            csv_substitutions(self.teacher_substitutions),
+            csv_substitutions(self.room_substitutions),
+        ]
+    }
+}
+
+/// Verbindet Ersetzungen zu einer CSV Zelle im Format "original>ersatz;original>ersatz"
+fn csv_substitutions(substitutions: &[Substitution]) -> String {
+    substitutions
+        .iter()
+        .map(|substitution| format!("{}{}{}", substitution.original, CSV_SUBSTITUTION_SEPARATOR, substitution.replacement))
+        .collect::<Vec<_>>()
+        .join(CSV_LIST_SEPARATOR)
+}
+
+/// Gibt die Zeilen aller Unterrichtsstunden aller Snapshots einer ExportFile zurück
+pub fn rows(export_file: &ExportFile) -> impl Iterator<Item = LessonRow<'_>> {
+    export_file.lessons().map(|(snapshot, lesson)| LessonRow::new(snapshot, lesson))
+}
+
+/// 'RowWriter' schreibt die Zeilen mehrerer Tagesdateien in eine gemeinsame Ausgabe
+pub enum RowWriter<W: Write> {
+    /// Ausgabe als CSV
+    Csv(csv::Writer<W>),
+    /// Ausgabe als JSON Lines
+    Jsonl(W),
+}
+
+impl<W: Write> RowWriter<W> {
+    /// Erstellt einen neuen RowWriter. Bei CSV wird die Kopfzeile sofort geschrieben.
+    ///
+    /// # Arguments
+    /// * `format` - Format der Ausgabe, muss `Csv` oder `Jsonl` sein
+    /// * `writer` - Ausgabe in die geschrieben wird
+    pub fn new(format: ExportFormat, writer: W) -> Result<Self> {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(LessonRow::CSV_HEADER)?;
+                Ok(RowWriter::Csv(writer))
+            }
+            ExportFormat::Jsonl => Ok(RowWriter::Jsonl(writer)),
+            _ => anyhow::bail!("Das Format {} wird nicht zeilenweise geschrieben", format),
+        }
+    }
+
+    /// Schreibt alle Zeilen einer ExportFile
+    ///
+    /// # Returns
+    /// * `usize` - Anzahl der geschriebenen Zeilen
+    pub fn write_export_file(&mut self, export_file: &ExportFile) -> Result<usize> {
+        let mut count = 0;
+        for row in rows(export_file) {
+            match self {
+                RowWriter::Csv(writer) => writer.write_record(row.csv_record())?,
+                RowWriter::Jsonl(writer) => {
+                    serde_json::to_writer(&mut *writer, &row)?;
+                    writeln!(writer)?;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Schreibt alle gepufferten Daten in die Ausgabe
+    pub fn finish(self) -> Result<()> {
+        match self {
+            RowWriter::Csv(mut writer) => writer.flush()?,
+            RowWriter::Jsonl(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Exportiert Tagesdateien in das angegebene Format
+///
+/// # Arguments
+/// * `files` - Tagesdateien die exportiert werden sollen
+/// * `format` - Format der Ausgabe
+/// * `writer` - Ausgabe in die geschrieben wird
+///
+/// # Returns
+/// * `usize` - Anzahl der exportierten Zeilen bzw. Tagesdateien bei JSON
+pub fn export_files<W: Write>(files: &[PathBuf], format: ExportFormat, mut writer: W) -> Result<usize> {
+    if format == ExportFormat::Json {
+        // Das JSON Dokument enthält genau eine Tagesdatei
+        let [file] = files else {
+            anyhow::bail!("Das Format json unterstützt nur eine Tagesdatei, für Zeiträume csv oder jsonl verwenden");
+        };
+        let export_file = ExportFile::read(file)?;
+        serde_json::to_writer_pretty(&mut writer, &export_file)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        return Ok(1);
+    }
+
+    let mut row_writer = RowWriter::new(format, writer)?;
+    let mut count = 0;
+    for file in files {
+        count += row_writer.write_export_file(&ExportFile::read(file)?)?;
+    }
+    row_writer.finish()?;
+    Ok(count)
+}
@@ -0,0 +1,51 @@
+//! School-Mining-Scraper liest die Stundenpläne von WebUntis aus und speichert sie als Snapshots in Tagesdateien.
+//!
+//! Die Bibliothek stellt das Datenformat der Tagesdateien bereit, damit andere Programme (z.B. die Aufbereitung für die Datenbank)
+//! die Dateien lesen können:
+//!
+//! * [`ExportFile`], [`Snapshot`], [`Lesson`] und [`LessonCode`] sind die deserialisierten Daten. Dateien älterer Versionen
+//!   werden beim Lesen in die aktuelle Version überführt.
+//! * [`ArchivedSnapshot`], [`ArchivedLesson`], [`ArchivedLessonCode`] und [`ArchivedMasterData`] sind die archivierten Daten,
+//!   auf die über den [`ArchiveReader`] ohne Kopieren zugegriffen werden kann. Der [`ArchiveReader`] ist die Sicht auf die
+//!   gesamte Tagesdatei, ein `ArchivedExportFile` gibt es nicht, da die Datei aus einzelnen Einträgen besteht.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use school_mining_scraper::ExportFile;
+//!
+//! let export_file = ExportFile::read(Path::new("storage/2023/10/16.bin")).unwrap();
+//! for snapshot in &export_file {
+//!     println!("{}: {} Unterrichtsstunden", snapshot.datetime(), snapshot.lessons().len());
+//! }
+//! ```
+
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod calendar;
+pub mod commands;
+pub mod config;
+pub mod daemon;
+pub mod data;
+pub mod diff;
+pub mod export;
+pub mod master_data;
+pub mod privacy;
+pub mod pseudonym;
+pub mod reader;
+pub mod request;
+pub mod scraper;
+pub mod state;
+pub mod storage;
+pub mod vault;
+
+#[cfg(test)]
+mod testing;
+
+pub use data::{
+    ArchivedFetchErrorKind, ArchivedFetchFailure, ArchivedLesson, ArchivedLessonCode, ArchivedRunMetadata, ArchivedSnapshot,
+    ArchivedSnapshotKind, ArchivedSubject, ArchivedSubstitution, ExportFile, FetchErrorKind, FetchFailure, Lesson, LessonCode,
+    LessonKey, RunMetadata, Snapshot, SnapshotKind, Subject, Substitution,
+};
+pub use master_data::{ArchivedMasterData, MasterData};
+pub use reader::ArchiveReader;
+pub use storage::{ArchiveError, SnapshotReader};
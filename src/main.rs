@@ -1,160 +1,167 @@
-use chrono::{DateTime, Utc};
-use data::{ExportFile, Snapshot};
-use dotenvy;
+use clap::{Args, Parser, Subcommand};
+use chrono::NaiveDate;
 use flexi_logger::{Logger, LoggerHandle, FileSpec, FlexiLoggerError};
-use serde::{Deserialize, Serialize};
-use std::env;
-use untis::Date; 
-use sha2::{Digest, Sha256};
-use crate::data::Lesson;
-use log::{error,  info,  trace};
-
-mod data;
-
-type Result<T> = anyhow::Result<T>;
+use std::{env, path::{Path, PathBuf}, process::ExitCode};
+use anyhow::Context;
+use log::{error, info};
+use school_mining_scraper::{
+    commands,
+    config::{load_config, load_dotenv, load_storage_path, load_vault_secret_key, Config},
+    daemon,
+    export::ExportFormat,
+    storage,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+/// Cli repräsentiert die Argumente des Programms
+struct Cli {
+    /// Unterbefehl der ausgeführt werden soll, ohne Unterbefehl wird `scrape` ausgeführt
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-#[derive(Debug)]
-/// Config repräsentiert die Konfiguration die aus der .env Datei geladen wird.
-struct Config {
-    /// Server auf dem Untis läuft
-    server: String,
-    /// Schule für die der Stundenplan abgerufen werden soll
-    school: String,
-    /// Benutzername für den Untis Account
-    user: String,
-    /// Passwort für den Untis Account
-    password: String,
-    /// Secret für die Pseudonymisierung der Lehrernamen
-    secret: String,
-    /// Pfad an dem die Daten gespeichert werden sollen
-    path: String,
-    /// Pfad an dem die Status Datei gespeichert werden soll
-    state_file_path: Option<String>,
-    /// URL unter der die Status Datei abgerufen werden kann
-    state_file_check: Option<String>,
+#[derive(Debug, Subcommand)]
+/// Command repräsentiert die Unterbefehle des Programms
+enum Command {
+    /// Ruft den Stundenplan ab und speichert einen neuen Snapshot
+    Scrape,
+    /// Läuft dauerhaft und ruft den Stundenplan zu den Zeitpunkten aus SCHEDULE ab
+    Daemon,
+    /// Ruft den Stundenplan vergangener Tage ab und speichert ihn als nachträgliche Snapshots in den Tagesdateien der Tage
+    Backfill {
+        /// Erster Tag der abgerufen werden soll (YYYY-MM-DD)
+        #[arg(long)]
+        from: NaiveDate,
+        /// Letzter Tag der abgerufen werden soll (YYYY-MM-DD)
+        #[arg(long)]
+        to: NaiveDate,
+    },
+    /// Gibt die Snapshots einer Tagesdatei und die Anzahl ihrer Unterrichtsstunden aus
+    Inspect(DayFile),
+    /// Exportiert eine Tagesdatei oder einen Zeitraum als JSON, CSV, JSON Lines oder (mit dem Feature `parquet`) als Parquet bzw. Arrow Datensatz
+    Export {
+        #[command(flatten)]
+        day: DayFile,
+        /// Exportiert alle Tagesdateien unter STORAGE_PATH
+        #[arg(long, conflicts_with_all = ["date", "file", "from"])]
+        all: bool,
+        /// Erster Tag des Zeitraums (YYYY-MM-DD)
+        #[arg(long, requires = "to", conflicts_with_all = ["date", "file"])]
+        from: Option<NaiveDate>,
+        /// Letzter Tag des Zeitraums (YYYY-MM-DD)
+        #[arg(long, requires = "from")]
+        to: Option<NaiveDate>,
+        /// Format der Ausgabe: json, csv, jsonl, parquet oder arrow
+        #[arg(long, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Datei in die exportiert werden soll, ohne Angabe wird auf die Standardausgabe geschrieben
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Validiert Tagesdateien, ohne Angabe werden alle Dateien unter STORAGE_PATH geprüft
+    Verify {
+        /// Datum der Tagesdatei
+        #[arg(long, conflicts_with = "file")]
+        date: Option<NaiveDate>,
+        /// Pfad zu einer Tagesdatei
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Überführt Tagesdateien älterer Versionen in die aktuelle Version, ohne Angabe alle Dateien unter STORAGE_PATH
+    Migrate {
+        /// Datum der Tagesdatei
+        #[arg(long, conflicts_with = "file")]
+        date: Option<NaiveDate>,
+        /// Pfad zu einer Tagesdatei
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Erstellt eine verschlüsselte Verknüpfungstabelle von den Pseudonymen eines alten Schlüssels zu denen eines neuen Schlüssels
+    Linkage {
+        /// Id des alten Schlüssels, `legacy` für Pseudonyme vor der Einführung von HMAC
+        #[arg(long)]
+        from: String,
+        /// Id des neuen Schlüssels
+        #[arg(long)]
+        to: String,
+    },
+    /// Löst ein Pseudonym über den Tresor auf, benötigt VAULT_SECRET_KEY. Jede Abfrage wird im Audit Log protokolliert.
+    Reveal {
+        /// Pseudonym das aufgelöst werden soll
+        pseudonym: String,
+        /// Grund der Abfrage, wird im Audit Log gespeichert
+        #[arg(long)]
+        reason: String,
+    },
+    /// Erzeugt ein neues Schlüsselpaar für den Tresor, der geheime Schlüssel wird in eine neue Datei geschrieben
+    VaultKeygen {
+        /// Datei für den geheimen Schlüssel, wird mit Leserechten nur für den eigenen Benutzer angelegt und darf noch nicht existieren
+        #[arg(long)]
+        secret_key_file: PathBuf,
+    },
+    /// Vergleicht zwei Snapshots einer Tagesdatei
+    Diff {
+        #[command(flatten)]
+        day: DayFile,
+        /// Index des älteren Snapshots, standardmäßig der vorletzte
+        #[arg(long)]
+        old: Option<usize>,
+        /// Index des neueren Snapshots, standardmäßig der letzte
+        #[arg(long)]
+        new: Option<usize>,
+    },
 }
 
+#[derive(Debug, Args)]
+/// DayFile wählt eine Tagesdatei aus, ohne Angabe wird die Tagesdatei von heute verwendet
+struct DayFile {
+    /// Datum der Tagesdatei (YYYY-MM-DD)
+    #[arg(long, conflicts_with = "file")]
+    date: Option<NaiveDate>,
+    /// Pfad zu einer Tagesdatei
+    #[arg(long)]
+    file: Option<PathBuf>,
+}
 
-/// Lädt die .env Datei und überschreibt die bereits gesetzten Variablen
-fn load_dotenv(){
-    // Lädt die .env Datei, wenn sie nicht gefunden wird wird eine Fehlermeldung ausgegeben.
-    if let Err(_) = dotenvy::dotenv_override() {
-        println!("Failed to load \".env\" file.");
+impl DayFile {
+    /// Gibt den Pfad der ausgewählten Tagesdatei zurück, STORAGE_PATH wird nur ohne angegebene Datei benötigt
+    fn path(&self) -> anyhow::Result<PathBuf> {
+        commands::resolve_day_file(storage_path, self.date, self.file.as_deref())
     }
 }
 
-/// Lädt die Konfiguration aus der .env Datei
-fn load_config() -> Result<Config> {
+/// Lädt die vollständige Konfiguration, die für die Befehle benötigt wird, die Untis abfragen
+fn config() -> anyhow::Result<Config> {
+    load_config().context("Laden der Konfiguration fehlgeschlagen")
+}
 
-    // Lädt die Variablen aus der .env Datei, wenn eine Variable nicht gefunden wird, wird ein Fehler zurückgegeben.
-    Ok(Config {
-        server: env::var("SERVER")?,
-        school: env::var("SCHOOL")?,
-        user: env::var("USERNAME")?,
-        password: env::var("PASSWORD")?,
-        secret: env::var("SECRET")?,
-        path: env::var("STORAGE_PATH")?,
-        state_file_path: env::var("STATE_PATH").ok(),
-        state_file_check: env::var("STATE_CHECK_URL").ok(),
-    })
+/// Lädt nur STORAGE_PATH, die übrigen Befehle benötigen weder die Zugangsdaten für Untis noch SECRET
+fn storage_path() -> anyhow::Result<String> {
+    load_storage_path().context("Laden der Konfiguration fehlgeschlagen")
 }
 
-/// Erstellt einen Snapshot des Stundenplans
+/// Löst ein Pseudonym auf, dafür werden nur der Tresor unter STORAGE_PATH und VAULT_SECRET_KEY benötigt
 ///
 /// # Arguments
-/// * `client` - Untis Client mit dem die Daten abgerufen werden sollen
-/// * `secret` - Das Secret die Pseudonymisierung der Lehrernamen benötigt wird
-///
-/// # Returns
-/// * `Snapshot` - Snapshot des Stundenplans
-
-fn create_snapshot(client: &mut untis::Client, secret: &str) -> Result<Snapshot> {
-    // Erstellt einen neuen Snapshot
-    let mut snapshot = Snapshot::new();
-
-    // Lädt alle Klassen der Schule
-    let classes = client.classes().unwrap();
-
-    // Füge die Stundenpläne der Klassen zum Snapshot hinzu
-    classes.iter().for_each(|class| {
-
-        trace!("Lade Stundenplan für Klasse: {}", class.name);
-        // Lädt den Stundenplan der Klasse
-        match client.timetable_between(
-            &class.id,
-            &untis::ElementType::Class,
-            &Date::today(),
-            &Date::today(),
-        ) {
-            Ok(lessons) => {
-                // Gehe durch alle Stunden und füge sie zum Snapshot hinzu
-                lessons.iter().for_each(|lesson| {
-                    // Wandelt die Lesson in eine Lesson um, die in der ExportDatei gespeichert werden kann
-                    let mut lesson: Lesson = lesson.into();
-
-                    // Pseudonymisiere die Lehrernamen
-                    let teachers = lesson
-                        .teachers
-                        .iter()
-                        .map(|teacher| {
-                            // Erstellt einen Hash aus dem Secret und dem Lehrernamen
-                            let mut hasher = Sha256::new();
-                            hasher.update(secret);
-                            hasher.update(teacher);
-
-                            // Gibt den Hash als Hex String zurück
-                            format!("{:x}", hasher.finalize())
-                        })
-                        .collect();
-                    // Speichert die pseudonymisierten Lehrernamen
-                    lesson.teachers = teachers;
-
-                    // Fügt die Lesson zum Snapshot hinzu
-                    snapshot.add_lesson(lesson)
-                })
-            }
-            Err(e) => {
-                error!("Error: {:#?}", e)
-            }
-        }
-    });
-
-    Ok(snapshot)
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-/// Status repräsentiert den Status des Programms
-pub enum State {
-    /// Das Programm wurde erfolgreich ausgeführt
-    SUCCESS,
-    /// Das Programm wurde mit einem Fehler beendet
-    ERROR(String),
-    /// Das Programm wurde gestartet und läuft noch
-    STARTED,
+/// * `pseudonym` - Pseudonym das aufgelöst werden soll
+/// * `reason` - Grund der Abfrage
+fn reveal(pseudonym: &str, reason: &str) -> anyhow::Result<()> {
+    let storage_path = storage_path()?;
+    let secret_key = load_vault_secret_key().context("Laden der Konfiguration fehlgeschlagen")?;
+    commands::reveal(&storage_path, &secret_key, pseudonym, reason)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-/// ReportedState repräsentiert den Status des Programms der in der Status Datei gespeichert wird
-pub struct ReportedState {
-    /// Status des Programms
-    state: State,
-    /// Zeitpunkt zu dem der Status gesetzt wurde
-    timestamp: DateTime<Utc>,
-}
-
-/// Aktualisiert den Status des Programms
+/// Gibt die ausgewählten Tagesdateien zurück, ohne Angabe alle Tagesdateien unter STORAGE_PATH
 ///
 /// # Arguments
-/// * `path` - Pfad an dem die Status Datei gespeichert werden soll
-/// * `state` - Status der gesetzt werden soll
-fn update_state(path: &str, state: State) -> Result<()> {
-    let state = ReportedState {
-        state,
-        timestamp: Utc::now(),
-    };
-    let state = serde_json::to_string(&state)?;
-    std::fs::write(path, state)?;
-    Ok(())
+/// * `date` - Datum der Tagesdatei
+/// * `file` - Pfad zu einer Tagesdatei
+fn day_files(date: Option<NaiveDate>, file: Option<&Path>) -> anyhow::Result<Vec<PathBuf>> {
+    match (date, file) {
+        (None, None) => commands::day_files(&storage_path()?),
+        (date, file) => Ok(vec![commands::resolve_day_file(storage_path, date, file)?]),
+    }
 }
 
 /// Erstellt einen Logger mit den Log Leveln die in der .env Datei gesetzt sind.
@@ -170,7 +177,8 @@ fn init_logger() -> std::result::Result<LoggerHandle,FlexiLoggerError>{
     .start()
 }
 
-fn main() {
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
     load_dotenv();
     let _logger = match init_logger(){
@@ -178,153 +186,41 @@ fn main() {
         Err(e) => {
             let error_msg = format!("Logger konnte nicht erstellt werden. {:#?}",e);
             println!("{}", error_msg); 
-            return;
+            return ExitCode::FAILURE;
         }
     };
     info!("Logger wurde erstellt.");
-    // Lädt die Konfiguration aus der .env Datei, wenn eine Variable nicht gefunden wird, wird das Programm beendet.
-    let config = match load_config() {
-        Ok(config) => config,
-        Err(_) => {
-            let error_msg = "Laden der Konfiguration fehlgeschlagen.";
-            error!("{}", error_msg); 
-            return;
+    // Die Konfiguration wird erst von den Befehlen geladen, damit jeder Befehl nur die Variablen benötigt die er verwendet
+    let result = match cli.command.unwrap_or(Command::Scrape) {
+        Command::Scrape => config().map(|config| commands::scrape(&config)),
+        Command::Daemon => config().and_then(|config| daemon::run(&config)),
+        Command::Backfill { from, to } => config().and_then(|config| commands::backfill(&config, from, to)),
+        Command::Inspect(day) => day.path().and_then(|path| commands::inspect(&path)),
+        Command::Export { day, all, from, to, format, output } => {
+            // Bei einem Zeitraum werden alle vorhandenen Tagesdateien exportiert
+            let files = match (all, from, to) {
+                (true, _, _) => storage_path().and_then(|storage_path| commands::day_files(&storage_path)),
+                (false, Some(from), Some(to)) => storage_path()
+                    .map(|storage_path| storage::day_paths_between(&storage_path, from, to).into_iter().map(|(_, path)| path).collect()),
+                _ => day.path().map(|path| vec![path]),
+            };
+            files.and_then(|files| commands::export(&files, format, output.as_deref()))
         }
+        // Ohne Angabe werden alle Tagesdateien geprüft
+        Command::Verify { date, file } => day_files(date, file.as_deref()).and_then(|paths| commands::verify(&paths)),
+        // Ohne Angabe werden alle Tagesdateien überführt
+        Command::Migrate { date, file } => day_files(date, file.as_deref()).and_then(|paths| commands::migrate(&paths)),
+        Command::Linkage { from, to } => config().and_then(|config| commands::linkage(&config, &from, &to)),
+        Command::Reveal { pseudonym, reason } => reveal(&pseudonym, &reason),
+        Command::VaultKeygen { secret_key_file } => commands::vault_keygen(&secret_key_file),
+        Command::Diff { day, old, new } => day.path().and_then(|path| commands::diff(&path, old, new)),
     };
- 
-    // Wenn STATE_CHECK_URL gesetzt ist wird der Status des Programms auf dem Hauptserver abgefragt
-    if let Some(status_file_check) = &config.state_file_check {
-        match reqwest::blocking::get(status_file_check) {
-            // Wenn der Status erfolgreich abgerufen wurde, wird überprüft ob das Programm bereits läuft oder erfolgreich ausgeführt wurde
-            Ok(response) => {
-                if response.status().is_success() {
-                    // Deserialisiert den Status
-                    let state: ReportedState = response.json().unwrap();
-                    // Prüfe ob der Status vor weniger als einer Stunde gesetzt wurde
-                    if state.timestamp + chrono::Duration::hours(1) > Utc::now() {
-                        // Wenn der Status vor weniger als einer Stunde gesetzt wurde, wird überprüft ob das Programm bereits läuft oder erfolgreich ausgeführt wurde
-                        match state.state {
-                            // Wenn das Programm bereits läuft wird eine Meldung ausgegeben und das Programm beendet
-                            State::STARTED => {
-                                info!("Das Programm läuft auf den Hauptserver bereits.");
-                                return;
-                            }
-                            // Wenn das Programm erfolgreich ausgeführt wurde wird eine Meldung ausgegeben und das Programm beendet
-                            State::SUCCESS => {
-                                info!("Das Programm wurde auf den Hauptserver erfolgreich ausgeführt.");
-                                return;
-                            }
-
-                            // Wenn das Programm mit einem Fehler beendet wurde wird eine Meldung ausgegeben und das Programm wird fortgesetzt
-                            State::ERROR(error_msg) => {
-                                error!("Hauptserver hat den Fehler: \"{}\"", error_msg);
-                                info!("Daten werden Lokal abgerufen.")
-                            }
-                        }
-                    }
-                }
-            }
 
-            // Wenn der Status nicht erfolgreich abgerufen werden konnte wird eine Meldung ausgegeben und das Programm wird fortgesetzt
-            Err(e) => {
-                let error_msg = format!("Fehler beim abrufen des Status: \"{:#?}\"", e);
-                error!("{}", error_msg);
-                info!("Daten werden abgerufen.");
-            }
-        }
-    }
-
-    // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf STARTED gesetzt
-    if let Some(path) = &config.state_file_path {
-        if let Err(e) = update_state(path, State::STARTED) {
-            let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-            error!("{}", error_msg);
-        }
-    }
-
-    // Erstellt einen neuen Client und loggt sich ein. Wenn das Login fehlschlägt wird eine Fehlermeldung ausgegeben und das Programm beendet.
-    let mut client = match untis::Client::login(
-        &config.server,
-        &config.school,
-        &config.user,
-        &config.password,
-    ) {
-        Ok(client) => client,
-        Err(e) => {
-            let error_msg = format!("Login fehlgeschlagen. {:#?}", e);
-            error!("{}", error_msg);
-
-            // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf ERROR gesetzt
-            if let Some(path) = &config.state_file_path {
-                if let Err(e) = update_state(path, State::ERROR(error_msg)) {
-                    let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-                    error!("{}", error_msg);
-                }
-            }
-            return;
-        }
-    };
-
-    // Lädt die ExportDatei, wenn sie nicht existiert wird eine neue erstellt.
-    let mut export_file = match ExportFile::load(&config.path) {
-        Ok(export_file) => export_file,
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            let error_msg = format!("Fehler beim Laden der ExportFile. {:#?}", e);
-            error!("{}", error_msg);
-
-            // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf ERROR gesetzt
-            if let Some(path) = &config.state_file_path {
-                if let Err(e) = update_state(path, State::ERROR(error_msg)) {
-                    let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-                    error!("{}", error_msg);
-                }
-            }
-            return;
-        }
-    };
-
-    let snapshot = {
-        match create_snapshot(&mut client, &config.secret) {
-            Ok(snapshot) => snapshot,
-            Err(e) => {
-                let error_msg = format!("Fehler beim erstellen des Snapshots. {:#?}", e);
-                error!("{}", error_msg);
-
-                // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf ERROR gesetzt
-                if let Some(path) = &config.state_file_path {
-                    if let Err(e) = update_state(path, State::ERROR(error_msg)) {
-                        let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-                        error!("{}", error_msg);
-                    }
-                }
-                return;
-            }
-        }
-    };
-
-    // Füge den Snapshot zur ExportDatei hinzu
-    export_file.add(snapshot);
-
-    // Speichert die ExportDatei
-    if let Err(e) = export_file.save(&config.path) {
-        let error_msg = format!("Fehler beim Speichern der ExportFile. {:#?}", e);
-        error!("{}", error_msg);
-
-        // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf ERROR gesetzt
-        if let Some(path) = &config.state_file_path {
-            if let Err(e) = update_state(path, State::ERROR(error_msg)) {
-                let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-                error!("{}", error_msg);
-            }
-        }
-        return;
-    } 
-    // Wenn STATE_PATH gesetzt ist wird der Status des Programms auf SUCCESS gesetzt
-    if let Some(path) = &config.state_file_path {
-        if let Err(e) = update_state(path, State::SUCCESS) {
-            let error_msg = format!("Fehler beim setzen des Status. {:#?}", e);
-            error!("{}", error_msg);
+            error!("{:#}", e);
+            ExitCode::FAILURE
         }
     }
-    info!("Daten wurden erfolgreich abgerufen.")
 }
@@ -0,0 +1,276 @@
+//! Stammdaten der Schule aus Untis.
+//!
+//! Die Stammdaten (Klassen, Räume, Fächer, Lehrer, Raster der Unterrichtsstunden, Ferien und das Schuljahr) werden bei jedem
+//! Durchlauf abgerufen und über einen Hash ihres Inhalts versioniert. Jeder Snapshot verweist auf die Version der Stammdaten,
+//! die zu seinem Zeitpunkt galt. In einer Tagesdatei wird jede Version nur einmal als eigener Eintrag gespeichert.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use log::warn;
+use rkyv::{Archive, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    privacy::{Anonymizer, FieldPolicy, LessonField},
+    pseudonym::Domain,
+    request::Client,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[archive(check_bytes)]
+/// 'MasterData' enthält die Stammdaten der Schule zum Zeitpunkt des Abrufs
+pub struct MasterData {
+    /// Version der Stammdaten, Hash über ihren Inhalt
+    version: String,
+    /// Zeitpunkt zu dem die Stammdaten abgerufen wurden
+    fetched: DateTime<Utc>,
+    /// Aktuelles Schuljahr
+    pub school_year: Option<SchoolYear>,
+    /// Klassen der Schule
+    pub classes: Vec<Element>,
+    /// Räume der Schule
+    pub rooms: Vec<Element>,
+    /// Fächer der Schule
+    pub subjects: Vec<Element>,
+    /// Pseudonymisierte Lehrer der Schule
+    pub teachers: Vec<String>,
+    /// Raster der Unterrichtsstunden je Wochentag
+    pub timegrid: Vec<TimeUnit>,
+    /// Ferien und unterrichtsfreie Tage
+    pub holidays: Vec<Holiday>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[archive(check_bytes)]
+/// 'Element' repräsentiert eine Klasse, einen Raum oder ein Fach in Untis
+pub struct Element {
+    /// Id des Elements in Untis
+    pub id: usize,
+    /// Kurzname des Elements (z.B. "10a" oder "M")
+    pub name: String,
+    /// Langname des Elements (z.B. "Mathematik")
+    pub long_name: String,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[archive(check_bytes)]
+/// 'SchoolYear' repräsentiert ein Schuljahr
+pub struct SchoolYear {
+    /// Id des Schuljahrs in Untis
+    pub id: usize,
+    /// Name des Schuljahrs (z.B. "2023/2024")
+    pub name: String,
+    /// Erster Tag des Schuljahrs
+    pub start: NaiveDate,
+    /// Letzter Tag des Schuljahrs
+    pub end: NaiveDate,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[archive(check_bytes)]
+/// 'TimeUnit' repräsentiert eine Unterrichtsstunde im Raster der Schule
+pub struct TimeUnit {
+    /// Wochentag wie in Untis (1 = Sonntag, 2 = Montag, ..., 7 = Samstag)
+    pub weekday: u8,
+    /// Name der Stunde (z.B. "1")
+    pub name: String,
+    /// Beginn der Stunde
+    pub start_time: NaiveTime,
+    /// Ende der Stunde
+    pub end_time: NaiveTime,
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[archive(check_bytes)]
+/// 'Holiday' repräsentiert Ferien oder einen unterrichtsfreien Tag
+pub struct Holiday {
+    /// Kurzname der Ferien
+    pub name: String,
+    /// Langname der Ferien (z.B. "Herbstferien")
+    pub long_name: String,
+    /// Erster Tag der Ferien
+    pub start: NaiveDate,
+    /// Letzter Tag der Ferien
+    pub end: NaiveDate,
+}
+
+impl MasterData {
+    /// Erstellt die Stammdaten und berechnet ihre Version
+    ///
+    /// # Returns
+    /// * `MasterData` - Stammdaten mit dem aktuellen Zeitpunkt als Zeitpunkt des Abrufs
+    pub fn new(
+        school_year: Option<SchoolYear>,
+        classes: Vec<Element>,
+        rooms: Vec<Element>,
+        subjects: Vec<Element>,
+        teachers: Vec<String>,
+        timegrid: Vec<TimeUnit>,
+        holidays: Vec<Holiday>,
+    ) -> Result<Self> {
+        let mut master_data = Self {
+            version: String::new(),
+            fetched: Utc::now(),
+            school_year,
+            classes,
+            rooms,
+            subjects,
+            teachers,
+            timegrid,
+            holidays,
+        };
+        master_data.version = master_data.content_hash()?;
+        Ok(master_data)
+    }
+
+    /// Ruft die Stammdaten aus Untis ab. Nur die Klassen sind erforderlich, alle anderen Stammdaten werden
+    /// bei einem Fehler (z.B. fehlende Berechtigung des Accounts) mit einer Warnung ausgelassen.
+    /// Klassen, Räume und Lehrer werden nach der Datenschutz Richtlinie gespeichert.
+    ///
+    /// # Arguments
+    /// * `client` - Untis Client mit dem die Daten abgerufen werden sollen
+    /// * `anonymizer` - Anonymizer mit der Datenschutz Richtlinie
+    pub fn fetch(client: &mut Client, anonymizer: &Anonymizer) -> Result<Self> {
+        let classes = anonymizer.apply_elements(
+            LessonField::Classes,
+            client
+                .call("Klassen", |client| client.classes())?
+                .iter()
+                .map(|class| Element { id: class.id, name: class.name.clone(), long_name: class.long_name.clone() })
+                .collect(),
+        );
+        let rooms = anonymizer.apply_elements(
+            LessonField::Rooms,
+            or_empty(
+                "Räume",
+                client.call("Räume", |client| client.rooms()).map(|rooms| {
+                    rooms.iter().map(|room| Element { id: room.id, name: room.name.clone(), long_name: room.long_name.clone() }).collect()
+                }),
+            ),
+        );
+        let subjects = or_empty(
+            "Fächer",
+            client.call("Fächer", |client| client.subjects()).map(|subjects| {
+                subjects
+                    .iter()
+                    .map(|subject| Element { id: subject.id, name: subject.name.clone(), long_name: subject.long_name.clone() })
+                    .collect()
+            }),
+        );
+        let mut teachers: Vec<String> = match anonymizer.policy().teachers {
+            FieldPolicy::Drop => Vec::new(),
+            _ => or_empty(
+                "Lehrer",
+                client.call("Lehrer", |client| client.teachers()).map(|teachers| {
+                    teachers.iter().map(|teacher| anonymizer.pseudonymizer().pseudonymize(Domain::Teacher, &teacher.name)).collect()
+                }),
+            ),
+        };
+        // Die Reihenfolge von Untis würde Rückschlüsse auf die Namen zulassen
+        teachers.sort();
+        let timegrid = or_empty(
+            "Stundenraster",
+            client.call("Stundenraster", |client| client.timegrid()).map(|days| {
+                days.iter()
+                    .flat_map(|day| {
+                        day.time_units.iter().map(move |unit| TimeUnit {
+                            weekday: day.day,
+                            name: unit.name.clone(),
+                            start_time: unit.start_time.0,
+                            end_time: unit.end_time.0,
+                        })
+                    })
+                    .collect()
+            }),
+        );
+        let holidays = or_empty("Ferien", fetch_holidays(client));
+        let school_year = match fetch_school_year(client) {
+            Ok(year) => Some(year),
+            Err(e) => {
+                warn!("Schuljahr konnte nicht abgerufen werden. {:#?}", e);
+                None
+            }
+        };
+
+        Self::new(school_year, classes, rooms, subjects, teachers, timegrid, holidays)
+    }
+
+    /// Gibt die Version der Stammdaten zurück
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Gibt den Zeitpunkt zurück, zu dem die Stammdaten abgerufen wurden
+    pub fn fetched(&self) -> DateTime<Utc> {
+        self.fetched
+    }
+
+    /// Gibt zurück ob das angegebene Datum in den Ferien liegt
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        self.holidays.iter().any(|holiday| holiday.start <= date && date <= holiday.end)
+    }
+
+    /// Berechnet den Hash über den Inhalt der Stammdaten. Version und Zeitpunkt des Abrufs fließen nicht ein,
+    /// damit unveränderte Stammdaten bei jedem Abruf dieselbe Version erhalten.
+    fn content_hash(&self) -> Result<String> {
+        let content = serde_json::to_vec(&(
+            &self.school_year,
+            &self.classes,
+            &self.rooms,
+            &self.subjects,
+            &self.teachers,
+            &self.timegrid,
+            &self.holidays,
+        ))?;
+        Ok(format!("{:x}", Sha256::digest(content)))
+    }
+}
+
+impl ArchivedMasterData {
+    /// Gibt die Version der archivierten Stammdaten zurück
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+}
+
+/// Ruft die Ferien und unterrichtsfreien Tage aus Untis ab
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem die Ferien abgerufen werden
+pub(crate) fn fetch_holidays(client: &mut Client) -> Result<Vec<Holiday>> {
+    Ok(client
+        .call("Ferien", |client| client.holidays())?
+        .iter()
+        .map(|holiday| Holiday {
+            name: holiday.name.clone(),
+            long_name: holiday.long_name.clone(),
+            start: holiday.start_date.0,
+            end: holiday.end_date.0,
+        })
+        .collect())
+}
+
+/// Ruft das aktuelle Schuljahr aus Untis ab
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem das Schuljahr abgerufen wird
+pub(crate) fn fetch_school_year(client: &mut Client) -> Result<SchoolYear> {
+    let year = client.call("Schuljahr", |client| client.current_schoolyear())?;
+    Ok(SchoolYear { id: year.id, name: year.name.clone(), start: year.start_date.0, end: year.end_date.0 })
+}
+
+/// Gibt die abgerufenen Stammdaten zurück oder loggt den Fehler und gibt eine leere Liste zurück
+///
+/// # Arguments
+/// * `what` - Bezeichnung der Stammdaten für die Log Meldung
+/// * `result` - Ergebnis des Abrufs
+fn or_empty<T, E: std::fmt::Debug>(what: &str, result: std::result::Result<Vec<T>, E>) -> Vec<T> {
+    match result {
+        Ok(items) => items,
+        Err(e) => {
+            warn!("{} konnten nicht abgerufen werden. {:#?}", what, e);
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! Datenschutz Richtlinie für die Felder einer Unterrichtsstunde.
+//!
+//! Für jedes Feld wird über `PRIVACY_POLICY` festgelegt, ob es unverändert gespeichert (`keep`), pseudonymisiert (`hash`),
+//! verworfen (`drop`) oder bereinigt (`scrub`) wird. Beim Bereinigen von Freitexten werden die Namen aller Lehrer aus der
+//! Lehrerliste von Untis unabhängig von Groß- und Kleinschreibung durch ihre Pseudonyme ersetzt, bevor der Text gespeichert
+//! wird. Ohne Namen zum Ersetzen werden die zu bereinigenden Freitexte verworfen.
+
+use std::{fmt, str::FromStr};
+
+use log::warn;
+
+use crate::{
+    config::Config,
+    data::Lesson,
+    master_data::Element,
+    pseudonym::{Domain, Pseudonymizer},
+    request::Client,
+    vault::Identity,
+};
+
+/// Mindestlänge eines Namens, damit er beim Bereinigen ersetzt wird. Kürzere Namen würden zu viele Wörter treffen.
+const MIN_SCRUB_NAME_LEN: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'FieldPolicy' legt fest wie ein Feld gespeichert wird
+pub enum FieldPolicy {
+    /// Das Feld wird unverändert gespeichert
+    Keep,
+    /// Das Feld wird pseudonymisiert
+    Hash,
+    /// Das Feld wird nicht gespeichert
+    Drop,
+    /// Namen von Lehrern werden im Freitext durch ihre Pseudonyme ersetzt
+    Scrub,
+}
+
+impl FromStr for FieldPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(FieldPolicy::Keep),
+            "hash" => Ok(FieldPolicy::Hash),
+            "drop" => Ok(FieldPolicy::Drop),
+            "scrub" => Ok(FieldPolicy::Scrub),
+            _ => Err(format!("Unbekannte Richtlinie \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for FieldPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldPolicy::Keep => write!(f, "keep"),
+            FieldPolicy::Hash => write!(f, "hash"),
+            FieldPolicy::Drop => write!(f, "drop"),
+            FieldPolicy::Scrub => write!(f, "scrub"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'LessonField' repräsentiert die Felder einer Unterrichtsstunde, für die eine Richtlinie festgelegt werden kann
+pub enum LessonField {
+    /// Klassen, auch in den Stammdaten
+    Classes,
+    /// Lehrer und ersetzte Lehrer
+    Teachers,
+    /// Räume und ersetzte Räume, auch in den Stammdaten
+    Rooms,
+    /// Beschreibung der Unterrichtsstunde
+    Description,
+    /// Vertretungshinweis der Unterrichtsstunde
+    SubText,
+}
+
+impl LessonField {
+    /// Gibt zurück ob das Feld ein Freitext ist, nur Freitexte können bereinigt werden
+    fn is_text(&self) -> bool {
+        matches!(self, LessonField::Description | LessonField::SubText)
+    }
+}
+
+impl FromStr for LessonField {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "classes" => Ok(LessonField::Classes),
+            "teachers" => Ok(LessonField::Teachers),
+            "rooms" => Ok(LessonField::Rooms),
+            "description" => Ok(LessonField::Description),
+            "sub_text" => Ok(LessonField::SubText),
+            _ => Err(format!("Unbekanntes Feld \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for LessonField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LessonField::Classes => write!(f, "classes"),
+            LessonField::Teachers => write!(f, "teachers"),
+            LessonField::Rooms => write!(f, "rooms"),
+            LessonField::Description => write!(f, "description"),
+            LessonField::SubText => write!(f, "sub_text"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'FieldRule' ist ein Eintrag in `PRIVACY_POLICY` im Format `<Feld>=<Richtlinie>`
+pub struct FieldRule {
+    /// Feld für das die Richtlinie gilt
+    pub field: LessonField,
+    /// Richtlinie des Feldes
+    pub policy: FieldPolicy,
+}
+
+impl FromStr for FieldRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (field, policy) = value.split_once('=').ok_or_else(|| format!("\"{}\" muss das Format <Feld>=<Richtlinie> haben", value.trim()))?;
+        Ok(Self { field: field.parse()?, policy: policy.parse()? })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'PrivacyPolicy' enthält die Richtlinien aller Felder
+pub struct PrivacyPolicy {
+    /// Richtlinie für die Klassen
+    pub classes: FieldPolicy,
+    /// Richtlinie für die Lehrer, nur `hash` oder `drop`
+    pub teachers: FieldPolicy,
+    /// Richtlinie für die Räume
+    pub rooms: FieldPolicy,
+    /// Richtlinie für die Beschreibung
+    pub description: FieldPolicy,
+    /// Richtlinie für den Vertretungshinweis
+    pub sub_text: FieldPolicy,
+}
+
+impl Default for PrivacyPolicy {
+    /// Lehrer werden pseudonymisiert, alle anderen Felder werden wie vor der Einführung von `PRIVACY_POLICY` unverändert gespeichert
+    fn default() -> Self {
+        Self {
+            classes: FieldPolicy::Keep,
+            teachers: FieldPolicy::Hash,
+            rooms: FieldPolicy::Keep,
+            description: FieldPolicy::Keep,
+            sub_text: FieldPolicy::Keep,
+        }
+    }
+}
+
+impl PrivacyPolicy {
+    /// Erstellt die Richtlinie aus den Einträgen von `PRIVACY_POLICY`, nicht angegebene Felder behalten ihre Standardrichtlinie
+    ///
+    /// # Returns
+    /// * `Err` - Wenn eine Richtlinie für das Feld nicht zulässig ist
+    pub fn from_rules(rules: &[FieldRule]) -> std::result::Result<Self, String> {
+        let mut policy = Self::default();
+        for rule in rules {
+            if rule.policy == FieldPolicy::Scrub && !rule.field.is_text() {
+                return Err(format!("{} ist kein Freitext und kann nicht bereinigt werden", rule.field));
+            }
+            if rule.field == LessonField::Teachers && rule.policy == FieldPolicy::Keep {
+                return Err("Lehrer dürfen nicht unverändert gespeichert werden".to_string());
+            }
+            *policy.get_mut(rule.field) = rule.policy;
+        }
+        Ok(policy)
+    }
+
+    /// Gibt die Richtlinie eines Feldes zurück
+    pub fn get(&self, field: LessonField) -> FieldPolicy {
+        match field {
+            LessonField::Classes => self.classes,
+            LessonField::Teachers => self.teachers,
+            LessonField::Rooms => self.rooms,
+            LessonField::Description => self.description,
+            LessonField::SubText => self.sub_text,
+        }
+    }
+
+    /// Gibt die Richtlinie eines Feldes zum Ändern zurück
+    fn get_mut(&mut self, field: LessonField) -> &mut FieldPolicy {
+        match field {
+            LessonField::Classes => &mut self.classes,
+            LessonField::Teachers => &mut self.teachers,
+            LessonField::Rooms => &mut self.rooms,
+            LessonField::Description => &mut self.description,
+            LessonField::SubText => &mut self.sub_text,
+        }
+    }
+
+    /// Gibt zurück ob mindestens ein Freitext bereinigt wird
+    fn scrubs(&self) -> bool {
+        self.description == FieldPolicy::Scrub || self.sub_text == FieldPolicy::Scrub
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// 'Scrubber' ersetzt die Namen von Lehrern in Freitexten durch ihre Pseudonyme
+pub struct Scrubber {
+    /// Paare aus Name und Pseudonym, längere Namen zuerst
+    replacements: Vec<(String, String)>,
+}
+
+impl Scrubber {
+    /// Erstellt einen Scrubber aus Paaren von Namen und Pseudonymen. Ein Lehrer kann mit mehreren Namen
+    /// (z.B. Kürzel und Nachname) vorkommen.
+    pub fn new(replacements: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut replacements: Vec<(String, String)> =
+            replacements.into_iter().filter(|(name, _)| name.trim().chars().count() >= MIN_SCRUB_NAME_LEN).collect();
+        // Längere Namen zuerst, damit z.B. "Müller-Lüdenscheidt" nicht nur teilweise ersetzt wird
+        replacements.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.to_lowercase().cmp(&b.to_lowercase())));
+        replacements.dedup_by(|(a, _), (b, _)| a.to_lowercase() == b.to_lowercase());
+        Self { replacements }
+    }
+
+    /// Gibt zurück ob der Scrubber keine Namen enthält und daher keinen Text bereinigen kann
+    pub fn is_empty(&self) -> bool {
+        self.replacements.is_empty()
+    }
+
+    /// Ersetzt alle Namen, die als ganzes Wort im Text vorkommen, unabhängig von Groß- und Kleinschreibung durch ihre Pseudonyme
+    pub fn scrub(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (name, pseudonym) in &self.replacements {
+            text = replace_word(&text, name, pseudonym);
+        }
+        text
+    }
+}
+
+/// Gibt die Länge in Bytes zurück, mit der der Text mit dem Wort beginnt. Groß- und Kleinschreibung wird nicht beachtet.
+///
+/// # Returns
+/// * `None` - Wenn der Text nicht mit dem Wort beginnt
+fn match_len(text: &str, word: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    for expected in word.chars() {
+        let (_, actual) = chars.next()?;
+        if !actual.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(text.len(), |(position, _)| position))
+}
+
+/// Ersetzt alle Vorkommen eines Wortes, die nicht Teil eines längeren Wortes sind. Groß- und Kleinschreibung wird nicht beachtet.
+fn replace_word(text: &str, word: &str, replacement: &str) -> String {
+    if word.is_empty() {
+        return text.to_string();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut position = 0;
+    while let Some(current) = text[position..].chars().next() {
+        let before = text[..position].chars().next_back();
+        if before.map_or(true, |c| !c.is_alphanumeric()) {
+            if let Some(len) = match_len(&text[position..], word) {
+                let after = text[position + len..].chars().next();
+                if after.map_or(true, |c| !c.is_alphanumeric()) {
+                    result.push_str(&text[last..position]);
+                    result.push_str(replacement);
+                    position += len;
+                    last = position;
+                    continue;
+                }
+            }
+        }
+        position += current.len_utf8();
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// 'Anonymizer' wendet die Datenschutz Richtlinie auf Unterrichtsstunden und Stammdaten an
+pub struct Anonymizer {
+    /// Richtlinien der Felder
+    policy: PrivacyPolicy,
+    /// Pseudonymizer für alle Felder mit der Richtlinie `hash`
+    pseudonymizer: Pseudonymizer,
+    /// Scrubber für Freitexte, `None` wenn die Lehrerliste nicht abgerufen werden konnte oder keine Namen enthält
+    scrubber: Option<Scrubber>,
+    /// Lehrerliste aus Untis, `None` wenn sie weder zum Bereinigen noch für den Tresor benötigt wird oder nicht abgerufen werden konnte
+    teachers: Option<Vec<Identity>>,
+}
+
+impl Anonymizer {
+    /// Erstellt einen Anonymizer. Wird ein Freitext bereinigt oder ist ein Tresor konfiguriert, wird die Lehrerliste einmal
+    /// aus Untis abgerufen. Schlägt dies fehl oder enthält sie keine Namen, werden die zu bereinigenden Freitexte verworfen,
+    /// damit keine Namen gespeichert werden.
+    ///
+    /// # Arguments
+    /// * `client` - Untis Client mit dem die Lehrerliste abgerufen wird
+    /// * `config` - Konfiguration mit der Datenschutz Richtlinie und dem Tresor
+    /// * `pseudonymizer` - Pseudonymizer mit dem Schlüssel des Tages
+    pub fn new(client: &mut Client, config: &Config, pseudonymizer: Pseudonymizer) -> Self {
+        let scrubs = config.privacy_policy.scrubs();
+        let teachers = if scrubs || config.vault_public_key.is_some() {
+            match client.call("Lehrer", |client| client.teachers()) {
+                Ok(teachers) => Some(
+                    teachers
+                        .iter()
+                        .map(|teacher| Identity { name: teacher.name.clone(), long_name: teacher.long_name.clone() })
+                        .collect::<Vec<_>>(),
+                ),
+                Err(e) => {
+                    warn!("Lehrer konnten nicht abgerufen werden. {:#?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let scrubber = match &teachers {
+            Some(teachers) if scrubs => Some(Scrubber::new(teachers.iter().flat_map(|teacher| {
+                let pseudonym = pseudonymizer.pseudonymize(Domain::Teacher, &teacher.name);
+                [(teacher.name.clone(), pseudonym.clone()), (teacher.long_name.clone(), pseudonym)]
+            })))
+            .filter(|scrubber| {
+                if scrubber.is_empty() {
+                    warn!("Die Lehrerliste enthält keine Namen, Freitexte werden verworfen statt bereinigt.");
+                }
+                !scrubber.is_empty()
+            }),
+            None if scrubs => {
+                warn!("Ohne Lehrerliste werden Freitexte verworfen statt bereinigt.");
+                None
+            }
+            _ => None,
+        };
+        Self { policy: config.privacy_policy, pseudonymizer, scrubber, teachers }
+    }
+
+    /// Gibt die Datenschutz Richtlinie zurück
+    pub fn policy(&self) -> &PrivacyPolicy {
+        &self.policy
+    }
+
+    /// Gibt die Lehrerliste zurück, die beim Erstellen abgerufen wurde
+    ///
+    /// # Returns
+    /// * `None` - Wenn die Lehrerliste nicht benötigt wurde oder nicht abgerufen werden konnte
+    pub fn teachers(&self) -> Option<&[Identity]> {
+        self.teachers.as_deref()
+    }
+
+    /// Gibt den Pseudonymizer zurück
+    pub fn pseudonymizer(&self) -> &Pseudonymizer {
+        &self.pseudonymizer
+    }
+
+    /// Gibt die Id des Schlüssels zurück, mit dem pseudonymisiert wird
+    pub fn key_id(&self) -> &str {
+        self.pseudonymizer.key_id()
+    }
+
+    /// Wendet die Richtlinie auf alle Felder einer Unterrichtsstunde an
+    pub fn apply(&self, lesson: &mut Lesson) {
+        self.apply_list(LessonField::Classes, Domain::Class, &mut lesson.classes);
+        self.apply_list(LessonField::Teachers, Domain::Teacher, &mut lesson.teachers);
+        self.apply_list(LessonField::Rooms, Domain::Room, &mut lesson.rooms);
+        for (field, domain, substitutions) in [
+            (LessonField::Teachers, Domain::Teacher, &mut lesson.teacher_substitutions),
+            (LessonField::Rooms, Domain::Room, &mut lesson.room_substitutions),
+        ] {
+            match self.policy.get(field) {
+                FieldPolicy::Drop => substitutions.clear(),
+                FieldPolicy::Hash => {
+                    for substitution in substitutions.iter_mut() {
+                        substitution.original = self.pseudonymizer.pseudonymize(domain, &substitution.original);
+                        substitution.replacement = self.pseudonymizer.pseudonymize(domain, &substitution.replacement);
+                    }
+                }
+                FieldPolicy::Keep | FieldPolicy::Scrub => {}
+            }
+        }
+        lesson.description = self.apply_text(LessonField::Description, &lesson.description).unwrap_or_default();
+        lesson.sub_text = lesson.sub_text.as_deref().and_then(|text| self.apply_text(LessonField::SubText, text));
+    }
+
+    /// Wendet die Richtlinie auf Elemente der Stammdaten (Klassen oder Räume) an
+    ///
+    /// # Arguments
+    /// * `field` - Feld der Unterrichtsstunde, dem die Elemente entsprechen
+    /// * `elements` - Elemente aus den Stammdaten
+    pub fn apply_elements(&self, field: LessonField, elements: Vec<Element>) -> Vec<Element> {
+        let domain = match field {
+            LessonField::Classes => Domain::Class,
+            LessonField::Rooms => Domain::Room,
+            _ => return elements,
+        };
+        match self.policy.get(field) {
+            FieldPolicy::Drop => Vec::new(),
+            FieldPolicy::Hash => elements
+                .into_iter()
+                .map(|element| Element {
+                    id: element.id,
+                    name: self.pseudonymizer.pseudonymize(domain, &element.name),
+                    // Der Langname würde das Pseudonym aufdecken
+                    long_name: String::new(),
+                })
+                .collect(),
+            FieldPolicy::Keep | FieldPolicy::Scrub => elements,
+        }
+    }
+
+    /// Wendet die Richtlinie auf eine Liste von Namen an
+    fn apply_list(&self, field: LessonField, domain: Domain, values: &mut Vec<String>) {
+        match self.policy.get(field) {
+            FieldPolicy::Drop => values.clear(),
+            FieldPolicy::Hash => {
+                for value in values.iter_mut() {
+                    *value = self.pseudonymizer.pseudonymize(domain, value);
+                }
+            }
+            FieldPolicy::Keep | FieldPolicy::Scrub => {}
+        }
+    }
+
+    /// Wendet die Richtlinie auf einen Freitext an
+    ///
+    /// # Returns
+    /// * `None` - Wenn der Text verworfen wird
+    fn apply_text(&self, field: LessonField, text: &str) -> Option<String> {
+        match self.policy.get(field) {
+            FieldPolicy::Keep => Some(text.to_string()),
+            FieldPolicy::Hash if text.is_empty() => Some(String::new()),
+            FieldPolicy::Hash => Some(self.pseudonymizer.pseudonymize(Domain::Text, text)),
+            FieldPolicy::Drop => None,
+            FieldPolicy::Scrub => self.scrubber.as_ref().map(|scrubber| scrubber.scrub(text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pseudonym::LEGACY_KEY_ID, testing::{lesson, test_config}};
+
+    /// Erstellt einen Scrubber für die Lehrerin Müller mit dem Kürzel MÜL
+    fn scrubber() -> Scrubber {
+        Scrubber::new([
+            ("MÜL".to_string(), "p1".to_string()),
+            ("Müller".to_string(), "p1".to_string()),
+            ("Müller-Lüdenscheidt".to_string(), "p2".to_string()),
+            ("M".to_string(), "p3".to_string()),
+        ])
+    }
+
+    #[test]
+    fn replace_word_only_replaces_whole_words() {
+        assert_eq!(replace_word("Müller", "Müller", "p1"), "p1");
+        assert_eq!(replace_word("für Müller, nicht Müllers", "Müller", "p1"), "für p1, nicht Müllers");
+        assert_eq!(replace_word("Müllerstraße und (Müller)", "Müller", "p1"), "Müllerstraße und (p1)");
+        assert_eq!(replace_word("ÄMüller Müller", "Müller", "p1"), "ÄMüller p1");
+        assert_eq!(replace_word("Müller", "", "p1"), "Müller");
+    }
+
+    #[test]
+    fn replace_word_ignores_case() {
+        assert_eq!(replace_word("MÜLLER und müller", "Müller", "p1"), "p1 und p1");
+        assert_eq!(replace_word("mül", "MÜL", "p1"), "p1");
+    }
+
+    #[test]
+    fn scrubber_replaces_longer_names_first() {
+        let scrubber = scrubber();
+        assert_eq!(scrubber.scrub("Vertretung für Müller-Lüdenscheidt"), "Vertretung für p2");
+        assert_eq!(scrubber.scrub("statt MÜL: Frau müller"), "statt p1: Frau p1");
+        // Zu kurze Namen würden zu viele Wörter treffen und werden nicht ersetzt
+        assert_eq!(scrubber.scrub("M 101"), "M 101");
+        assert!(!scrubber.is_empty());
+        assert!(Scrubber::new([("M".to_string(), "p3".to_string())]).is_empty());
+    }
+
+    #[test]
+    fn parses_policy() {
+        let rules: Vec<FieldRule> = ["rooms=hash", " Description = SCRUB ", "sub_text=drop"].iter().map(|rule| rule.parse().unwrap()).collect();
+        let policy = PrivacyPolicy::from_rules(&rules).unwrap();
+        assert_eq!(policy.get(LessonField::Classes), FieldPolicy::Keep);
+        assert_eq!(policy.get(LessonField::Teachers), FieldPolicy::Hash);
+        assert_eq!(policy.get(LessonField::Rooms), FieldPolicy::Hash);
+        assert_eq!(policy.get(LessonField::Description), FieldPolicy::Scrub);
+        assert_eq!(policy.get(LessonField::SubText), FieldPolicy::Drop);
+    }
+
+    #[test]
+    fn default_policy_keeps_texts() {
+        let policy = PrivacyPolicy::from_rules(&[]).unwrap();
+        assert_eq!(policy, PrivacyPolicy::default());
+        assert_eq!(policy.get(LessonField::Teachers), FieldPolicy::Hash);
+        assert_eq!(policy.get(LessonField::Description), FieldPolicy::Keep);
+        assert_eq!(policy.get(LessonField::SubText), FieldPolicy::Keep);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!("rooms".parse::<FieldRule>().is_err());
+        assert!("subject=hash".parse::<FieldRule>().is_err());
+        assert!("rooms=encrypt".parse::<FieldRule>().is_err());
+        assert!(PrivacyPolicy::from_rules(&["rooms=scrub".parse().unwrap()]).is_err());
+        assert!(PrivacyPolicy::from_rules(&["teachers=keep".parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn texts_are_dropped_without_scrubber() {
+        let config = test_config("");
+        let policy = PrivacyPolicy::from_rules(&["description=scrub".parse().unwrap(), "sub_text=scrub".parse().unwrap()]).unwrap();
+        let pseudonymizer = Pseudonymizer::for_key(&config, LEGACY_KEY_ID).unwrap();
+        let mut with_texts = lesson(1, "10a", 8);
+        with_texts.description = "Vertretung für Müller".to_string();
+        with_texts.sub_text = Some("statt Müller".to_string());
+
+        let mut dropped = with_texts.clone();
+        Anonymizer { policy, pseudonymizer: pseudonymizer.clone(), scrubber: None, teachers: None }.apply(&mut dropped);
+        assert_eq!(dropped.description, "");
+        assert_eq!(dropped.sub_text, None);
+
+        let mut scrubbed = with_texts;
+        Anonymizer { policy, pseudonymizer, scrubber: Some(scrubber()), teachers: None }.apply(&mut scrubbed);
+        assert_eq!(scrubbed.description, "Vertretung für p1");
+        assert_eq!(scrubbed.sub_text.as_deref(), Some("statt p1"));
+    }
+}
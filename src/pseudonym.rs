@@ -0,0 +1,438 @@
+//! Pseudonymisierung der Lehrernamen und weiterer Felder.
+//!
+//! Pseudonyme werden als HMAC-SHA256 über den Namen gebildet. Vor dem Namen steht die Domäne des Feldes (z.B. `teacher`),
+//! damit derselbe Name in unterschiedlichen Feldern nicht dasselbe Pseudonym erhält. Die Schlüssel werden über `PSEUDONYM_KEYS`
+//! konfiguriert und gelten jeweils ab einem Datum, üblicherweise ab Beginn eines Schuljahres. Jeder Snapshot speichert die Id
+//! des Schlüssels, mit dem seine Pseudonyme gebildet wurden. Solange `PSEUDONYM_KEYS` nicht gesetzt ist, werden Lehrer weiterhin
+//! wie vom ersten Scraper als `Sha256(SECRET || Name)` pseudonymisiert, damit die Pseudonyme bei einem Update gleich bleiben.
+//!
+//! Damit Auswertungen über einen Schlüsselwechsel hinweg möglich bleiben, kann eine Verknüpfungstabelle erstellt werden, die
+//! die alten Pseudonyme auf die neuen abbildet. Die Tabelle wird mit dem alten Schlüssel verschlüsselt, sodass sie nur lesen
+//! kann, wer die alten Pseudonyme ohnehin bilden könnte.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use chrono::{Duration, NaiveDate};
+use hmac::{Hmac, Mac};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, storage};
+
+type Result<T> = anyhow::Result<T>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Id des Schlüssels für Pseudonyme, die wie vor der Einführung von HMAC als `Sha256(SECRET || Name)` gebildet werden.
+/// Er wird verwendet, solange `PSEUDONYM_KEYS` nicht gesetzt ist.
+pub const LEGACY_KEY_ID: &str = "legacy";
+/// Kennung am Anfang jeder Verknüpfungstabelle
+const LINKAGE_MAGIC: [u8; 8] = *b"SMSLNK\0\0";
+/// Länge der Nonce von XChaCha20Poly1305
+const NONCE_LEN: usize = 24;
+/// Zeitraum nach dem ein Schlüssel rotiert werden sollte
+const ROTATION_INTERVAL_DAYS: i64 = 366;
+
+#[derive(Clone, PartialEq, Eq)]
+/// 'PseudonymKey' repräsentiert einen Schlüssel für die Pseudonymisierung, der ab einem Datum gilt
+pub struct PseudonymKey {
+    /// Id des Schlüssels, wird in jedem Snapshot gespeichert
+    pub id: String,
+    /// Erster Tag an dem der Schlüssel verwendet wird
+    pub valid_from: NaiveDate,
+    /// Geheimer Schlüssel
+    secret: String,
+}
+
+impl PseudonymKey {
+    /// Erstellt einen neuen Schlüssel
+    ///
+    /// # Arguments
+    /// * `id` - Id des Schlüssels
+    /// * `valid_from` - Erster Tag an dem der Schlüssel verwendet wird
+    /// * `secret` - Geheimer Schlüssel
+    pub fn new(id: &str, valid_from: NaiveDate, secret: &str) -> Self {
+        Self { id: id.to_string(), valid_from, secret: secret.to_string() }
+    }
+}
+
+impl FromStr for PseudonymKey {
+    type Err = String;
+
+    /// Liest einen Schlüssel im Format `<Gültig ab>=<Secret>` (z.B. `2024-08-01=geheim`), das Datum ist zugleich die Id
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (valid_from, secret) = value.trim().split_once('=').ok_or_else(|| "Schlüssel muss das Format <Datum>=<Secret> haben".to_string())?;
+        let valid_from = NaiveDate::parse_from_str(valid_from, "%Y-%m-%d").map_err(|e| format!("Ungültiges Datum \"{}\": {}", valid_from, e))?;
+        if secret.is_empty() {
+            return Err(format!("Schlüssel ab {} hat kein Secret", valid_from));
+        }
+        Ok(Self::new(&valid_from.to_string(), valid_from, secret))
+    }
+}
+
+impl fmt::Debug for PseudonymKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Das Secret darf nicht in den Logs landen
+        f.debug_struct("PseudonymKey").field("id", &self.id).field("valid_from", &self.valid_from).finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'Domain' gibt an für welches Feld ein Pseudonym gebildet wird
+pub enum Domain {
+    /// Namen von Lehrern
+    Teacher,
+    /// Namen von Klassen
+    Class,
+    /// Namen von Räumen
+    Room,
+    /// Freitexte (Beschreibung und Vertretungshinweis)
+    Text,
+}
+
+impl Domain {
+    /// Gibt die Bezeichnung der Domäne zurück, die in den HMAC einfließt
+    fn label(&self) -> &'static str {
+        match self {
+            Domain::Teacher => "teacher",
+            Domain::Class => "class",
+            Domain::Room => "room",
+            Domain::Text => "text",
+        }
+    }
+}
+
+#[derive(Clone)]
+/// 'Scheme' ist das Verfahren mit dem ein Pseudonymizer Pseudonyme bildet
+enum Scheme {
+    /// `Sha256(SECRET || Name)` für Lehrer wie im alten Format, die übrigen Domänen gab es dort nicht und verwenden HMAC-SHA256
+    Legacy(String),
+    /// HMAC-SHA256 mit Domäne
+    Hmac(String),
+}
+
+#[derive(Clone)]
+/// 'Pseudonymizer' bildet Pseudonyme mit einem bestimmten Schlüssel
+pub struct Pseudonymizer {
+    /// Id des verwendeten Schlüssels
+    key_id: String,
+    /// Verfahren und geheimer Schlüssel
+    scheme: Scheme,
+}
+
+impl Pseudonymizer {
+    /// Gibt den Pseudonymizer mit dem Schlüssel zurück, der am angegebenen Tag gilt. Ist `PSEUDONYM_KEYS` nicht gesetzt,
+    /// wird `SECRET` mit dem alten Verfahren (`legacy`) verwendet, damit sich die Pseudonyme ohne konfigurierte Schlüssel
+    /// nicht ändern. Der Wechsel auf HMAC ist erst mit `PSEUDONYM_KEYS` und einer Verknüpfungstabelle möglich.
+    ///
+    /// # Arguments
+    /// * `config` - Konfiguration mit den Schlüsseln
+    /// * `date` - Tag dessen Daten pseudonymisiert werden
+    ///
+    /// # Returns
+    /// * `Err` - Wenn an dem Tag noch kein Schlüssel gilt
+    pub fn for_date(config: &Config, date: NaiveDate) -> Result<Self> {
+        if config.pseudonym_keys.is_empty() {
+            return Ok(Self { key_id: LEGACY_KEY_ID.to_string(), scheme: Scheme::Legacy(config.secret.clone()) });
+        }
+
+        let key = config
+            .pseudonym_keys
+            .iter()
+            .filter(|key| key.valid_from <= date)
+            .max_by_key(|key| key.valid_from)
+            .ok_or_else(|| anyhow::anyhow!("Für den {} ist kein Schlüssel in PSEUDONYM_KEYS gültig", date))?;
+        if key.valid_from + Duration::days(ROTATION_INTERVAL_DAYS) < date {
+            warn!("Der Schlüssel {} ist älter als ein Jahr und sollte rotiert werden.", key.id);
+        }
+        Ok(Self { key_id: key.id.clone(), scheme: Scheme::Hmac(key.secret.clone()) })
+    }
+
+    /// Gibt den Pseudonymizer mit dem Schlüssel der angegebenen Id zurück
+    ///
+    /// # Arguments
+    /// * `config` - Konfiguration mit den Schlüsseln
+    /// * `key_id` - Id des Schlüssels, `legacy` für das Verfahren vor der Einführung von HMAC
+    pub fn for_key(config: &Config, key_id: &str) -> Result<Self> {
+        let scheme = match key_id {
+            LEGACY_KEY_ID => Scheme::Legacy(config.secret.clone()),
+            _ => {
+                let key = config
+                    .pseudonym_keys
+                    .iter()
+                    .find(|key| key.id == key_id)
+                    .ok_or_else(|| anyhow::anyhow!("Der Schlüssel {} ist nicht in PSEUDONYM_KEYS enthalten", key_id))?;
+                Scheme::Hmac(key.secret.clone())
+            }
+        };
+        Ok(Self { key_id: key_id.to_string(), scheme })
+    }
+
+    /// Gibt die Id des verwendeten Schlüssels zurück
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Bildet das Pseudonym eines Namens
+    ///
+    /// # Arguments
+    /// * `domain` - Feld zu dem der Name gehört
+    /// * `name` - Name der pseudonymisiert werden soll
+    ///
+    /// # Returns
+    /// * `String` - Pseudonym als Hex String
+    pub fn pseudonymize(&self, domain: Domain, name: &str) -> String {
+        match &self.scheme {
+            Scheme::Legacy(secret) if domain == Domain::Teacher => {
+                let mut hasher = Sha256::new();
+                hasher.update(secret);
+                hasher.update(name);
+                format!("{:x}", hasher.finalize())
+            }
+            Scheme::Legacy(secret) | Scheme::Hmac(secret) => {
+                let mut mac = hmac(secret);
+                mac.update(domain.label().as_bytes());
+                mac.update(&[0]);
+                mac.update(name.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            }
+        }
+    }
+
+    /// Leitet aus dem Schlüssel einen Schlüssel für die Verschlüsselung von Verknüpfungstabellen ab
+    fn linkage_cipher(&self) -> XChaCha20Poly1305 {
+        let secret = match &self.scheme {
+            Scheme::Legacy(secret) | Scheme::Hmac(secret) => secret,
+        };
+        derive_cipher(secret, format!("linkage\0{}", self.key_id).as_bytes())
+    }
+}
+
+impl fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pseudonymizer").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+/// Erstellt einen HMAC-SHA256 mit dem angegebenen Secret
+fn hmac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC akzeptiert Schlüssel beliebiger Länge")
+}
+
+/// Leitet aus einem Secret einen Schlüssel für XChaCha20Poly1305 ab
+///
+/// # Arguments
+/// * `secret` - Secret aus dem der Schlüssel abgeleitet wird
+/// * `context` - Verwendungszweck, damit für jeden Zweck ein anderer Schlüssel entsteht
+pub(crate) fn derive_cipher(secret: &str, context: &[u8]) -> XChaCha20Poly1305 {
+    let mut mac = hmac(secret);
+    mac.update(context);
+    XChaCha20Poly1305::new(Key::from_slice(&mac.finalize().into_bytes()))
+}
+
+/// Verschlüsselt Daten mit einer zufälligen Nonce
+///
+/// # Returns
+/// * `Vec<u8>` - Kennung | Nonce | verschlüsselte Daten
+pub(crate) fn seal(cipher: &XChaCha20Poly1305, magic: &[u8; 8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| anyhow::anyhow!("Die Daten konnten nicht verschlüsselt werden"))?;
+
+    let mut content = Vec::with_capacity(magic.len() + NONCE_LEN + ciphertext.len());
+    content.extend_from_slice(magic);
+    content.extend_from_slice(&nonce);
+    content.extend_from_slice(&ciphertext);
+    Ok(content)
+}
+
+/// Prüft die Kennung und entschlüsselt Daten, die mit `seal` verschlüsselt wurden
+///
+/// # Returns
+/// * `Err` - Wenn die Kennung nicht übereinstimmt oder die Daten mit dem Schlüssel nicht entschlüsselt werden können
+pub(crate) fn open(cipher: &XChaCha20Poly1305, magic: &[u8; 8], content: &[u8]) -> Result<Vec<u8>> {
+    if content.len() < magic.len() + NONCE_LEN || content[..magic.len()] != magic[..] {
+        anyhow::bail!("unbekanntes Dateiformat");
+    }
+    let (nonce, ciphertext) = content[magic.len()..].split_at(NONCE_LEN);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("falscher Schlüssel oder beschädigte Datei"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// 'Linkage' bildet die Pseudonyme eines alten Schlüssels auf die Pseudonyme eines neuen Schlüssels ab
+pub struct Linkage {
+    /// Id des alten Schlüssels
+    pub from: String,
+    /// Id des neuen Schlüssels
+    pub to: String,
+    /// Paare aus altem und neuem Pseudonym, sortiert nach dem alten Pseudonym
+    pub entries: Vec<(String, String)>,
+}
+
+impl Linkage {
+    /// Erstellt die Verknüpfungstabelle für die angegebenen Lehrernamen
+    ///
+    /// # Arguments
+    /// * `from` - Pseudonymizer mit dem alten Schlüssel
+    /// * `to` - Pseudonymizer mit dem neuen Schlüssel
+    /// * `names` - Namen der Lehrer, z.B. aus der aktuellen Lehrerliste von Untis
+    pub fn new(from: &Pseudonymizer, to: &Pseudonymizer, names: &[String]) -> Self {
+        let mut entries: Vec<(String, String)> = names
+            .iter()
+            .map(|name| (from.pseudonymize(Domain::Teacher, name), to.pseudonymize(Domain::Teacher, name)))
+            .collect();
+        // Die Reihenfolge der Namen würde Rückschlüsse auf die Lehrer zulassen
+        entries.sort();
+        entries.dedup();
+        Self { from: from.key_id().to_string(), to: to.key_id().to_string(), entries }
+    }
+
+    /// Gibt das neue Pseudonym zu einem alten Pseudonym zurück
+    pub fn get(&self, old: &str) -> Option<&str> {
+        self.entries
+            .binary_search_by(|(from, _)| from.as_str().cmp(old))
+            .ok()
+            .map(|position| self.entries[position].1.as_str())
+    }
+
+    /// Gibt den Pfad der Verknüpfungstabelle zwischen zwei Schlüsseln zurück
+    ///
+    /// # Arguments
+    /// * `storage` - Pfad an dem die Tagesdateien gespeichert werden
+    /// * `from` - Id des alten Schlüssels
+    /// * `to` - Id des neuen Schlüssels
+    pub fn path(storage: &str, from: &str, to: &str) -> PathBuf {
+        Path::new(storage).join("linkage").join(format!("{}_{}.bin", from, to))
+    }
+
+    /// Verschlüsselt die Verknüpfungstabelle mit dem alten Schlüssel und speichert sie unter `STORAGE_PATH/linkage`
+    ///
+    /// # Arguments
+    /// * `storage` - Pfad an dem die Tagesdateien gespeichert werden
+    /// * `from` - Pseudonymizer mit dem alten Schlüssel
+    ///
+    /// # Returns
+    /// * `PathBuf` - Pfad der gespeicherten Verknüpfungstabelle
+    pub fn write(&self, storage: &str, from: &Pseudonymizer) -> Result<PathBuf> {
+        if from.key_id() != self.from {
+            anyhow::bail!("Die Verknüpfungstabelle muss mit dem alten Schlüssel {} verschlüsselt werden", self.from);
+        }
+        let content = seal(&from.linkage_cipher(), &LINKAGE_MAGIC, &serde_json::to_vec(self)?)?;
+
+        let path = Self::path(storage, &self.from, &self.to);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Eine abgebrochene Verknüpfungstabelle darf eine bereits vorhandene nicht ersetzen
+        storage::write_atomic(&path, &content)?;
+        Ok(path)
+    }
+
+    /// Liest und entschlüsselt eine Verknüpfungstabelle
+    ///
+    /// # Arguments
+    /// * `path` - Pfad der Verknüpfungstabelle
+    /// * `from` - Pseudonymizer mit dem alten Schlüssel
+    pub fn read(path: &Path, from: &Pseudonymizer) -> Result<Self> {
+        let plaintext = open(&from.linkage_cipher(), &LINKAGE_MAGIC, &fs::read(path)?).map_err(|e| {
+            anyhow::anyhow!("Verknüpfungstabelle {} konnte mit dem Schlüssel {} nicht gelesen werden: {}", path.display(), from.key_id(), e)
+        })?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{temp_dir, test_config};
+
+    /// Erstellt eine Konfiguration mit Schlüsseln ab dem 01.08.2023 und dem 01.08.2024
+    fn config_with_keys() -> Config {
+        let mut config = test_config("");
+        config.pseudonym_keys = vec!["2023-08-01=alt".parse().unwrap(), "2024-08-01=neu".parse().unwrap()];
+        config
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn pseudonyms_are_stable() {
+        // Die erwarteten Werte sind fest, damit eine Änderung des Verfahrens nicht unbemerkt alle Pseudonyme verändert
+        let config = test_config("");
+        let legacy = Pseudonymizer::for_key(&config, LEGACY_KEY_ID).unwrap();
+        assert_eq!(legacy.pseudonymize(Domain::Teacher, "Müller"), "11a9ba5a3130fa0fdb2508a3a3a22d7315f70d4372600c0a931aa95cd1f57b94");
+        let hmac = Pseudonymizer::for_key(&config_with_keys(), "2023-08-01").unwrap();
+        assert_eq!(hmac.pseudonymize(Domain::Teacher, "Müller"), hmac.pseudonymize(Domain::Teacher, "Müller"));
+    }
+
+    #[test]
+    fn legacy_scheme_is_used_without_keys() {
+        // Ohne PSEUDONYM_KEYS müssen die Pseudonyme der Lehrer denen des ersten Scrapers entsprechen
+        let config = test_config("");
+        let pseudonymizer = Pseudonymizer::for_date(&config, date("2024-01-01")).unwrap();
+        assert_eq!(pseudonymizer.key_id(), LEGACY_KEY_ID);
+        assert_eq!(pseudonymizer.pseudonymize(Domain::Teacher, "Müller"), "11a9ba5a3130fa0fdb2508a3a3a22d7315f70d4372600c0a931aa95cd1f57b94");
+    }
+
+    #[test]
+    fn domains_have_different_pseudonyms() {
+        for key_id in [LEGACY_KEY_ID, "2023-08-01"] {
+            let pseudonymizer = Pseudonymizer::for_key(&config_with_keys(), key_id).unwrap();
+            assert_ne!(pseudonymizer.pseudonymize(Domain::Teacher, "A1"), pseudonymizer.pseudonymize(Domain::Room, "A1"));
+        }
+    }
+
+    #[test]
+    fn key_is_chosen_by_date() {
+        let config = config_with_keys();
+        assert_eq!(Pseudonymizer::for_date(&config, date("2024-07-31")).unwrap().key_id(), "2023-08-01");
+        assert_eq!(Pseudonymizer::for_date(&config, date("2024-08-01")).unwrap().key_id(), "2024-08-01");
+        assert!(Pseudonymizer::for_date(&config, date("2023-07-31")).is_err());
+
+        let by_date = Pseudonymizer::for_date(&config, date("2024-01-01")).unwrap();
+        let by_id = Pseudonymizer::for_key(&config, "2023-08-01").unwrap();
+        assert_eq!(by_date.pseudonymize(Domain::Teacher, "Müller"), by_id.pseudonymize(Domain::Teacher, "Müller"));
+        assert!(Pseudonymizer::for_key(&config, "2025-08-01").is_err());
+    }
+
+    #[test]
+    fn parses_keys() {
+        let key: PseudonymKey = "2024-08-01=geheim".parse().unwrap();
+        assert_eq!(key, PseudonymKey::new("2024-08-01", date("2024-08-01"), "geheim"));
+        assert!("geheim".parse::<PseudonymKey>().is_err());
+        assert!("2024-13-01=geheim".parse::<PseudonymKey>().is_err());
+        assert!("2024-08-01=".parse::<PseudonymKey>().is_err());
+        assert!(!format!("{:?}", key).contains("geheim"));
+    }
+
+    #[test]
+    fn linkage_is_encrypted_with_the_old_key() {
+        let config = config_with_keys();
+        let from = Pseudonymizer::for_key(&config, "2023-08-01").unwrap();
+        let to = Pseudonymizer::for_key(&config, "2024-08-01").unwrap();
+        let names = vec!["Müller".to_string(), "Schmidt".to_string()];
+        let linkage = Linkage::new(&from, &to, &names);
+        assert_eq!(
+            linkage.get(&from.pseudonymize(Domain::Teacher, "Schmidt")),
+            Some(to.pseudonymize(Domain::Teacher, "Schmidt").as_str())
+        );
+
+        let storage = temp_dir("linkage").display().to_string();
+        assert!(linkage.write(&storage, &to).is_err());
+        let path = linkage.write(&storage, &from).unwrap();
+        assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains(&from.pseudonymize(Domain::Teacher, "Müller")));
+        assert_eq!(Linkage::read(&path, &from).unwrap(), linkage);
+        assert!(Linkage::read(&path, &to).is_err());
+    }
+}
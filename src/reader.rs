@@ -0,0 +1,166 @@
+//! Lesender Zugriff auf die Tagesdateien ohne Kopieren der Daten.
+//!
+//! Der `ArchiveReader` blendet eine Tagesdatei in den Speicher ein und gibt die archivierten Strukturen
+//! (`ArchivedSnapshot`, `ArchivedLesson`, `ArchivedMasterData`) direkt zurück. Es werden keine `String`s oder `Vec`s erzeugt,
+//! wodurch auch Monate an Daten schnell durchsucht werden können. Da ältere Versionen eine andere Struktur haben,
+//! werden nur Dateien in der aktuellen Version gelesen, ältere Dateien müssen vorher mit `migrate` überführt werden.
+
+use std::{fs::File, marker::PhantomData, path::Path};
+
+use chrono::NaiveDate;
+use memmap2::Mmap;
+use rkyv::{check_archived_root, validation::validators::DefaultValidator, Archive, CheckBytes};
+
+use crate::{
+    data::Snapshot,
+    master_data::MasterData,
+    storage::{
+        self, ArchiveError, RecordHeader, FORMAT_VERSION, HEADER_LEN, MAGIC, RECORD_HEADER_LEN, RECORD_MASTER_DATA, RECORD_SNAPSHOT,
+    },
+};
+
+/// 'ArchiveReader' blendet eine Tagesdatei in den Speicher ein und ermöglicht den Zugriff auf die archivierten Daten ohne sie zu kopieren.
+pub struct ArchiveReader {
+    /// Pfad der Datei, wird für Fehlermeldungen benötigt
+    path: String,
+    /// Eingeblendete Datei
+    mmap: Mmap,
+}
+
+impl ArchiveReader {
+    /// Blendet die Tagesdatei am angegebenen Pfad in den Speicher ein
+    ///
+    /// # Arguments
+    /// * `path` - Pfad der Tagesdatei
+    ///
+    /// # Returns
+    /// * `ArchiveReader` - Reader für die Tagesdatei
+    /// * `ArchiveError::UnsupportedVersion` - Wenn die Datei mit einer anderen Version des Formats geschrieben wurde,
+    ///   Dateien im alten Format haben die Version 0
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let file = File::open(path)?;
+        // SAFETY: Einträge eines Snapshot Logs werden nach dem Schreiben nicht mehr verändert und Dateien älterer Versionen
+        // werden nur atomar ersetzt. Nur ein beschädigtes Ende eines Logs wird abgeschnitten, dieses wird beim Lesen ohnehin verworfen.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let path = path.display().to_string();
+
+        if mmap.len() < MAGIC.len() || mmap[..MAGIC.len()] != MAGIC {
+            return Err(ArchiveError::UnsupportedVersion { path, version: 0 });
+        }
+        if mmap.len() < HEADER_LEN {
+            return Err(ArchiveError::Corrupt { path, reason: "Dateikopf ist unvollständig".to_string() });
+        }
+        let version = u32::from_le_bytes([mmap[8], mmap[9], mmap[10], mmap[11]]);
+        if version != FORMAT_VERSION {
+            return Err(ArchiveError::UnsupportedVersion { path, version });
+        }
+        Ok(Self { path, mmap })
+    }
+
+    /// Gibt einen Iterator über die archivierten Snapshots der Datei zurück
+    pub fn snapshots(&self) -> ArchivedSnapshots<'_> {
+        ArchivedRecords::new(self, RECORD_SNAPSHOT)
+    }
+
+    /// Gibt einen Iterator über die archivierten Versionen der Stammdaten der Datei zurück
+    pub fn master_data(&self) -> ArchivedRecords<'_, MasterData> {
+        ArchivedRecords::new(self, RECORD_MASTER_DATA)
+    }
+}
+
+/// Iterator über die archivierten Snapshots einer eingeblendeten Tagesdatei
+pub type ArchivedSnapshots<'a> = ArchivedRecords<'a, Snapshot>;
+
+/// 'ArchivedRecords' ist ein Iterator über die archivierten Einträge einer Art in einer eingeblendeten Tagesdatei.
+/// Jeder Eintrag wird vor der Rückgabe validiert.
+pub struct ArchivedRecords<'a, T> {
+    /// Reader der Datei
+    reader: &'a ArchiveReader,
+    /// Art der Einträge, die zurückgegeben werden
+    kind: u32,
+    /// Position des nächsten Eintrags
+    offset: usize,
+    /// Gibt an ob das Ende der Datei oder ein Fehler erreicht wurde
+    done: bool,
+    /// Typ der archivierten Einträge
+    record: PhantomData<T>,
+}
+
+impl<'a, T> ArchivedRecords<'a, T> {
+    /// Erstellt einen Iterator, der beim ersten Eintrag nach dem Dateikopf beginnt
+    fn new(reader: &'a ArchiveReader, kind: u32) -> Self {
+        Self { reader, kind, offset: HEADER_LEN, done: false, record: PhantomData }
+    }
+}
+
+impl<'a, T> Iterator for ArchivedRecords<'a, T>
+where
+    T: Archive,
+    T::Archived: CheckBytes<DefaultValidator<'a>>,
+{
+    type Item = Result<&'a T::Archived, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = self.reader;
+        let bytes = &reader.mmap[..];
+
+        while !self.done && self.offset < bytes.len() {
+            let offset = self.offset;
+            let corrupt = |reason: &str| ArchiveError::Corrupt {
+                path: reader.path.clone(),
+                reason: format!("Eintrag bei Offset {} {}", offset, reason),
+            };
+
+            // Liest den Kopf des Eintrags
+            let Some(header) = bytes.get(offset..offset + RECORD_HEADER_LEN) else {
+                self.done = true;
+                return Some(Err(corrupt("ist unvollständig")));
+            };
+            let header = RecordHeader::parse(header.try_into().expect("Kopf hat die Länge RECORD_HEADER_LEN"));
+
+            // Die Daten liegen direkt hinter dem Kopf und sind durch die Auffüllung immer ausgerichtet
+            let start = offset + RECORD_HEADER_LEN;
+            if start + header.padded_len() > bytes.len() {
+                self.done = true;
+                return Some(Err(corrupt("ist unvollständig")));
+            }
+            let payload = &bytes[start..start + header.len];
+            if crc32fast::hash(payload) != header.checksum {
+                self.done = true;
+                return Some(Err(corrupt("hat eine falsche Prüfsumme")));
+            }
+
+            self.offset = start + header.padded_len();
+            // Unbekannte Einträge und Einträge anderer Art werden übersprungen
+            if header.kind != self.kind {
+                continue;
+            }
+
+            let record = check_archived_root::<T>(payload).map_err(|e| ArchiveError::Corrupt {
+                path: reader.path.clone(),
+                reason: format!("Eintrag bei Offset {}: {}", offset, e),
+            });
+            if record.is_err() {
+                self.done = true;
+            }
+            return Some(record);
+        }
+        None
+    }
+}
+
+/// Blendet alle Tagesdateien zwischen den angegebenen Daten ein. Tage ohne Datei werden übersprungen.
+///
+/// # Arguments
+/// * `storage_path` - Pfad an dem die Daten gespeichert werden
+/// * `from` - Erster Tag (inklusive)
+/// * `to` - Letzter Tag (inklusive)
+///
+/// # Returns
+/// * `Vec<(NaiveDate, ArchiveReader)>` - Reader für jeden Tag mit Datei
+pub fn open_range(storage_path: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<(NaiveDate, ArchiveReader)>, ArchiveError> {
+    storage::day_paths_between(storage_path, from, to)
+        .into_iter()
+        .map(|(date, path)| Ok((date, ArchiveReader::open(&path)?)))
+        .collect()
+}
@@ -0,0 +1,387 @@
+//! Anfragen an Untis mit Wiederholungen, Ratenbegrenzung und erneutem Login.
+//!
+//! Alle Anfragen laufen über den [`Client`]. Schlägt eine Anfrage vorübergehend fehl, wird sie mit exponentiell wachsender,
+//! zufällig verteilter Wartezeit wiederholt. Die Anzahl der Wiederholungen ist je Anfrage (`MAX_RETRIES`) und je Durchlauf
+//! (`RETRY_BUDGET`) begrenzt, damit ein gestörtes Untis nicht mit Anfragen überhäuft wird. Alle Clients eines Durchlaufs
+//! (z.B. die der Worker) teilen sich das Budget und die Begrenzung auf `REQUESTS_PER_SECOND` Anfragen pro Sekunde.
+//! Ist die Sitzung abgelaufen, loggt sich der Client neu ein.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc, Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use rand::Rng;
+
+use crate::config::Config;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Fehlercodes der WebUntis JSON-RPC Api, an denen eine abgelaufene Sitzung erkannt wird (nicht eingeloggt)
+const SESSION_EXPIRED_CODES: [i32; 1] = [-8520];
+/// Fehlercodes, bei denen eine Wiederholung nichts ändert (falsche Zugangsdaten, fehlende Berechtigung, Datum außerhalb des Schuljahrs)
+const PERMANENT_CODES: [i32; 3] = [-8504, -8509, -7004];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 'ErrorKind' ist die Art eines Fehlers bei einer Anfrage
+pub enum ErrorKind {
+    /// Die Sitzung ist abgelaufen, die Anfrage wird nach einem neuen Login wiederholt
+    SessionExpired,
+    /// Untis hat nicht innerhalb von `REQUEST_TIMEOUT` geantwortet
+    Timeout,
+    /// Die Anfrage wird auch bei einer Wiederholung fehlschlagen
+    Permanent,
+    /// Vorübergehender Fehler, z.B. ein Netzwerkfehler oder ein Fehler des Servers
+    Transient,
+}
+
+impl ErrorKind {
+    /// Bestimmt die Art eines Fehlers. Die Fehler des untis Crates werden an ihrer Variante und den Fehlercodes der Api
+    /// erkannt, auch wenn sie in einen Kontext eingebettet sind. Alle anderen Fehler gelten als vorübergehend.
+    ///
+    /// # Arguments
+    /// * `error` - Fehler der Anfrage
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<TimeoutError>().is_some() {
+            return ErrorKind::Timeout;
+        }
+        match error.chain().find_map(|cause| cause.downcast_ref::<untis::Error>()) {
+            Some(untis::Error::Rpc(rpc)) if SESSION_EXPIRED_CODES.contains(&rpc.code) => ErrorKind::SessionExpired,
+            Some(untis::Error::Rpc(rpc)) if PERMANENT_CODES.contains(&rpc.code) => ErrorKind::Permanent,
+            Some(untis::Error::Reqwest(http)) if http.is_timeout() => ErrorKind::Timeout,
+            _ => ErrorKind::Transient,
+        }
+    }
+
+    /// Gibt zurück ob eine Wiederholung der Anfrage sinnvoll ist
+    pub fn is_retryable(self) -> bool {
+        self != ErrorKind::Permanent
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::SessionExpired => write!(f, "session_expired"),
+            ErrorKind::Timeout => write!(f, "timeout"),
+            ErrorKind::Permanent => write!(f, "permanent"),
+            ErrorKind::Transient => write!(f, "transient"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// 'TimeoutError' wird zurückgegeben, wenn Untis nicht innerhalb von `REQUEST_TIMEOUT` antwortet
+pub struct TimeoutError(Duration);
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Zeitüberschreitung nach {} Sekunden", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// 'Limits' sind die Grenzen, die sich alle Clients eines Durchlaufs teilen
+struct Limits {
+    /// Zeit nach der eine Anfrage abgebrochen wird
+    timeout: Duration,
+    /// Höchste Anzahl an Wiederholungen einer Anfrage
+    max_retries: u32,
+    /// Wartezeit vor der ersten Wiederholung
+    base_delay: Duration,
+    /// Höchste Wartezeit vor einer Wiederholung
+    max_delay: Duration,
+    /// Anzahl der Wiederholungen je Durchlauf
+    budget_size: u32,
+    /// Verbleibende Wiederholungen im aktuellen Durchlauf
+    budget: AtomicU32,
+    /// Mindestabstand zwischen zwei Anfragen, `None` ohne Begrenzung
+    interval: Option<Duration>,
+    /// Frühester Zeitpunkt der nächsten Anfrage
+    next_request: Mutex<Instant>,
+}
+
+impl Limits {
+    /// Erstellt die Grenzen aus der Konfiguration
+    fn new(config: &Config) -> Self {
+        Self {
+            timeout: Duration::from_secs(config.request_timeout),
+            max_retries: config.max_retries,
+            base_delay: Duration::from_millis(config.retry_base_delay),
+            max_delay: Duration::from_millis(config.retry_max_delay),
+            budget_size: config.retry_budget,
+            budget: AtomicU32::new(config.retry_budget),
+            // `load_config` prüft den Wert, ungültige Werte schalten die Begrenzung ab statt einen Panic auszulösen
+            interval: (config.requests_per_second > 0.0)
+                .then(|| Duration::try_from_secs_f64(1.0 / config.requests_per_second).ok())
+                .flatten(),
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wartet, bis die nächste Anfrage gesendet werden darf
+    fn throttle(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let wait = {
+            let mut next_request = self.next_request.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let start = (*next_request).max(now);
+            *next_request = start + interval;
+            start - now
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Nimmt eine Wiederholung aus dem Budget
+    ///
+    /// # Returns
+    /// * `false` - Wenn das Budget des Durchlaufs aufgebraucht ist
+    fn take_retry(&self) -> bool {
+        self.budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |budget| budget.checked_sub(1)).is_ok()
+    }
+
+    /// Gibt die Wartezeit vor einer Wiederholung zurück. Die Wartezeit verdoppelt sich mit jedem Versuch
+    /// und liegt zufällig zwischen der Hälfte und dem vollen Wert, damit die Worker nicht gleichzeitig wiederholen.
+    ///
+    /// # Arguments
+    /// * `attempt` - Anzahl der bisherigen Wiederholungen der Anfrage
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// 'Credentials' sind die Zugangsdaten für den Login
+#[derive(Clone)]
+struct Credentials {
+    /// Server auf dem Untis läuft
+    server: String,
+    /// Schule in Untis
+    school: String,
+    /// Benutzername für den Untis Account
+    user: String,
+    /// Passwort für den Untis Account
+    password: String,
+}
+
+/// 'Client' sendet die Anfragen an Untis. Er loggt sich bei Bedarf neu ein und teilt sich die Grenzen mit seinen Workern,
+/// die über [`Client::workers`] erstellt werden. Wird der Client verworfen, meldet er seine Sitzung und die seiner Worker ab.
+pub struct Client {
+    /// Eingeloggter Client, `None` bis zum Login oder nach einer abgelaufenen Sitzung
+    inner: Option<untis::Client>,
+    /// Zugangsdaten für den erneuten Login
+    credentials: Credentials,
+    /// Gemeinsame Grenzen des Durchlaufs
+    limits: Arc<Limits>,
+    /// Worker mit eigener Sitzung, sie werden wie der Client selbst über mehrere Durchläufe weiterverwendet
+    workers: Vec<Client>,
+    /// Thread einer Anfrage, die nach einer Zeitüberschreitung noch läuft
+    stray: Option<thread::JoinHandle<()>>,
+}
+
+impl Client {
+    /// Loggt sich bei Untis ein
+    ///
+    /// # Arguments
+    /// * `config` - Konfiguration mit den Zugangsdaten und Grenzen
+    pub fn login(config: &Config) -> Result<Self> {
+        let mut client = Self {
+            inner: None,
+            credentials: Credentials {
+                server: config.server.clone(),
+                school: config.school.clone(),
+                user: config.user.clone(),
+                password: config.password.clone(),
+            },
+            limits: Arc::new(Limits::new(config)),
+            workers: Vec::new(),
+            stray: None,
+        };
+        client.call("Login", |_| Ok(()))?;
+        Ok(client)
+    }
+
+    /// Gibt Worker mit eigener Sitzung zurück, die sich die Grenzen mit diesem Client teilen. Fehlende Worker werden erstellt
+    /// und loggen sich erst bei ihrer ersten Anfrage ein, bereits erstellte Worker behalten ihre Sitzung.
+    ///
+    /// # Arguments
+    /// * `count` - Anzahl der Worker
+    pub fn workers(&mut self, count: usize) -> &mut [Client] {
+        while self.workers.len() < count {
+            let worker = Self {
+                inner: None,
+                credentials: self.credentials.clone(),
+                limits: Arc::clone(&self.limits),
+                workers: Vec::new(),
+                stray: None,
+            };
+            self.workers.push(worker);
+        }
+        &mut self.workers[..count]
+    }
+
+    /// Setzt das Budget der Wiederholungen für einen neuen Durchlauf zurück
+    pub fn reset_retry_budget(&self) {
+        self.limits.budget.store(self.limits.budget_size, Ordering::Relaxed);
+    }
+
+    /// Sendet eine Anfrage und wiederholt sie bei vorübergehenden Fehlern, solange Versuche und Budget reichen.
+    /// Ist die Sitzung abgelaufen, wird vor der Wiederholung neu eingeloggt.
+    ///
+    /// # Arguments
+    /// * `what` - Bezeichnung der Anfrage für die Log Meldungen
+    /// * `request` - Anfrage die mit dem eingeloggten untis Client gesendet wird
+    pub fn call<T, F>(&mut self, what: &str, request: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&mut untis::Client) -> std::result::Result<T, untis::Error> + Clone + Send + 'static,
+    {
+        let mut attempt = 0;
+        loop {
+            let error = match self.attempt(request.clone()) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let kind = ErrorKind::of(&error);
+            if kind == ErrorKind::SessionExpired {
+                self.inner = None;
+            }
+            if !kind.is_retryable() || attempt >= self.limits.max_retries || !self.limits.take_retry() {
+                return Err(error);
+            }
+            let delay = match kind {
+                // Nach einer abgelaufenen Sitzung genügt ein neuer Login
+                ErrorKind::SessionExpired => Duration::ZERO,
+                _ => self.limits.backoff(attempt),
+            };
+            warn!("{} fehlgeschlagen ({}), neuer Versuch in {} ms. {:#}", what, kind, delay.as_millis(), error);
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// Sendet eine Anfrage einmal. Die Anfrage läuft in einem eigenen Thread, damit sie nach `REQUEST_TIMEOUT` abgebrochen
+    /// werden kann. Der Thread einer abgebrochenen Anfrage meldet seine Sitzung selbst ab, sobald die Anfrage zurückkehrt,
+    /// und wird vor der nächsten Anfrage abgewartet. So läuft je Client höchstens eine abgebrochene Anfrage weiter.
+    fn attempt<T, F>(&mut self, request: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut untis::Client) -> std::result::Result<T, untis::Error> + Send + 'static,
+    {
+        self.join_stray();
+        let credentials = self.credentials.clone();
+        let inner = self.inner.take();
+        let limits = Arc::clone(&self.limits);
+
+        let (sender, receiver) = mpsc::channel();
+        // Nach einer Zeitüberschreitung nimmt der Aufrufer den Sender, der Thread erkennt daran, dass niemand mehr wartet
+        let sender = Arc::new(Mutex::new(Some(sender)));
+        let handle = thread::spawn({
+            let sender = Arc::clone(&sender);
+            move || {
+                let result = send(inner, &credentials, &limits, request);
+                let sender = sender.lock().unwrap_or_else(PoisonError::into_inner).take();
+                match sender {
+                    Some(sender) => {
+                        // Der Empfänger lebt, bis der Sender genommen wurde
+                        let _ = sender.send(result);
+                    }
+                    None => {
+                        if let Ok((client, _)) = result {
+                            logout(client);
+                        }
+                    }
+                }
+            }
+        });
+
+        let received = match receiver.recv_timeout(self.limits.timeout) {
+            Ok(received) => Some(received),
+            Err(_) => match sender.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                Some(_) => None,
+                // Der Thread hat das Ergebnis kurz nach Ablauf der Zeit doch noch gesendet
+                None => receiver.recv().ok(),
+            },
+        };
+
+        match received {
+            Some(Ok((client, result))) => {
+                self.inner = Some(client);
+                Ok(result?)
+            }
+            Some(Err(error)) => Err(anyhow::Error::from(error).context("Login fehlgeschlagen")),
+            None => {
+                self.stray = Some(handle);
+                Err(TimeoutError(self.limits.timeout).into())
+            }
+        }
+    }
+
+    /// Wartet auf den Thread einer abgebrochenen Anfrage. Die Anfrage endet spätestens mit dem Timeout des HTTP Clients.
+    fn join_stray(&mut self) {
+        if let Some(stray) = self.stray.take() {
+            if stray.join().is_err() {
+                warn!("Abgebrochene Anfrage an Untis ist mit einem Panic beendet worden");
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    /// Meldet die Sitzung ab, damit sie nicht bis zu ihrem Ablauf auf dem Server bestehen bleibt, und wartet auf eine
+    /// abgebrochene Anfrage, die ihre Sitzung selbst abmeldet. Die Worker werden danach mit ihren eigenen Sitzungen verworfen.
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            logout(inner);
+        }
+        self.join_stray();
+    }
+}
+
+/// Meldet eine Sitzung bei Untis ab. Ein Fehler wird nur geloggt, da die Sitzung auf dem Server ohnehin abläuft.
+///
+/// # Arguments
+/// * `client` - Eingeloggter untis Client
+fn logout(mut client: untis::Client) {
+    if let Err(e) = client.logout() {
+        warn!("Abmelden bei Untis fehlgeschlagen. {:?}", e);
+    }
+}
+
+/// Loggt sich bei Bedarf ein und sendet die Anfrage
+///
+/// # Returns
+/// * `(untis::Client, Result)` - Eingeloggter untis Client und Ergebnis der Anfrage
+/// * `Err` - Wenn der Login fehlgeschlagen ist
+fn send<T, F>(
+    inner: Option<untis::Client>,
+    credentials: &Credentials,
+    limits: &Limits,
+    request: F,
+) -> std::result::Result<(untis::Client, std::result::Result<T, untis::Error>), untis::Error>
+where
+    F: FnOnce(&mut untis::Client) -> std::result::Result<T, untis::Error>,
+{
+    let mut client = match inner {
+        Some(client) => client,
+        None => {
+            limits.throttle();
+            untis::Client::login(&credentials.server, &credentials.school, &credentials.user, &credentials.password)?
+        }
+    };
+    limits.throttle();
+    let result = request(&mut client);
+    Ok((client, result))
+}
@@ -0,0 +1,332 @@
+use std::{
+    env, fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Instant,
+};
+
+use chrono::{Datelike, Duration, Local, NaiveDate, Utc, Weekday};
+use log::{error, trace, warn};
+use untis::Date;
+
+use crate::{
+    config::{Config, ElementKind},
+    data::{FetchFailure, Lesson, RunMetadata, Snapshot},
+    master_data::MasterData,
+    privacy::Anonymizer,
+    request::{Client, ErrorKind},
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Verschiebt ein Datum um die angegebene Anzahl an Schultagen (Montag bis Freitag)
+///
+/// # Arguments
+/// * `date` - Datum das verschoben werden soll
+/// * `days` - Anzahl der Schultage, negative Werte verschieben in die Vergangenheit
+pub fn add_school_days(date: NaiveDate, days: i64) -> NaiveDate {
+    let step = Duration::days(days.signum());
+    let mut date = date;
+    let mut remaining = days.abs();
+    while remaining > 0 {
+        date += step;
+        if !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            remaining -= 1;
+        }
+    }
+    date
+}
+
+/// Gibt den Zeitraum zurück, dessen Stundenplan abgerufen wird
+///
+/// # Arguments
+/// * `today` - Heutiges Datum
+/// * `config` - Konfiguration mit der Anzahl der Schultage vor und nach heute
+///
+/// # Returns
+/// * `(NaiveDate, NaiveDate)` - Erster und letzter Tag des Zeitraums
+pub fn fetch_window(today: NaiveDate, config: &Config) -> (NaiveDate, NaiveDate) {
+    (
+        add_school_days(today, -i64::from(config.fetch_days_before)),
+        add_school_days(today, i64::from(config.fetch_days_ahead)),
+    )
+}
+
+/// Erstellt einen Snapshot des Stundenplans
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem die Daten abgerufen werden sollen
+/// * `config` - Konfiguration mit dem abzurufenden Zeitraum
+/// * `anonymizer` - Anonymizer mit der Datenschutz Richtlinie
+/// * `master_data` - Stammdaten auf die der Snapshot verweist
+///
+/// # Returns
+/// * `Snapshot` - Snapshot des Stundenplans
+pub fn create_snapshot(
+    client: &mut Client,
+    config: &Config,
+    anonymizer: &Anonymizer,
+    master_data: Option<&MasterData>,
+) -> Result<Snapshot> {
+    // Ermittelt den Zeitraum der abgerufen werden soll
+    let (window_start, window_end) = fetch_window(Local::now().date_naive(), config);
+
+    // Erstellt einen neuen Snapshot
+    let mut snapshot = Snapshot::new(window_start, window_end);
+    snapshot.set_master_data_version(master_data.map(|master_data| master_data.version().to_string()));
+    snapshot.set_pseudonym_key(Some(anonymizer.key_id().to_string()));
+    fetch_lessons(client, config, anonymizer, &mut snapshot)?;
+    Ok(snapshot)
+}
+
+/// Erstellt einen nachträglichen Snapshot des Stundenplans eines vergangenen Tages
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem die Daten abgerufen werden sollen
+/// * `config` - Konfiguration mit den abzurufenden Elementtypen
+/// * `anonymizer` - Anonymizer mit der Datenschutz Richtlinie
+/// * `date` - Tag dessen Stundenplan abgerufen werden soll
+/// * `master_data` - Stammdaten auf die der Snapshot verweist
+///
+/// # Returns
+/// * `Snapshot` - Snapshot mit der Art `SnapshotKind::Backfill`
+pub fn create_backfill_snapshot(
+    client: &mut Client,
+    config: &Config,
+    anonymizer: &Anonymizer,
+    date: NaiveDate,
+    master_data: Option<&MasterData>,
+) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::backfill(date, date);
+    snapshot.set_master_data_version(master_data.map(|master_data| master_data.version().to_string()));
+    snapshot.set_pseudonym_key(Some(anonymizer.key_id().to_string()));
+    fetch_lessons(client, config, anonymizer, &mut snapshot)?;
+    Ok(snapshot)
+}
+
+/// Lädt die Elemente (z.B. Klassen oder Räume) eines Elementtyps
+///
+/// # Returns
+/// * `Vec<usize>` - Ids der Elemente
+fn element_ids(client: &mut Client, kind: ElementKind) -> Result<Vec<usize>> {
+    let ids = match kind {
+        ElementKind::Class => client.call("Klassen", |client| client.classes())?.iter().map(|class| class.id).collect(),
+        ElementKind::Teacher => client.call("Lehrer", |client| client.teachers())?.iter().map(|teacher| teacher.id).collect(),
+        ElementKind::Room => client.call("Räume", |client| client.rooms())?.iter().map(|room| room.id).collect(),
+        ElementKind::Subject => client.call("Fächer", |client| client.subjects())?.iter().map(|subject| subject.id).collect(),
+    };
+    Ok(ids)
+}
+
+/// Gibt den Untis Elementtyp eines Elementtyps zurück
+fn untis_element_type(kind: ElementKind) -> untis::ElementType {
+    match kind {
+        ElementKind::Class => untis::ElementType::Class,
+        ElementKind::Teacher => untis::ElementType::Teacher,
+        ElementKind::Room => untis::ElementType::Room,
+        ElementKind::Subject => untis::ElementType::Subject,
+    }
+}
+
+/// 'Job' ist der Abruf des Stundenplans eines Elements
+#[derive(Debug, Clone, Copy)]
+struct Job {
+    /// Elementtyp des Elements
+    kind: ElementKind,
+    /// Id des Elements in Untis
+    id: usize,
+}
+
+/// Lädt die Stundenpläne aller Elemente der konfigurierten Elementtypen für den Zeitraum des Snapshots
+/// und fügt die Unterrichtsstunden dem Snapshot hinzu. Unterrichtsstunden die in mehreren Stundenplänen
+/// (z.B. bei zwei Klassen oder einer Klasse und einem Raum) enthalten sind, führt der Snapshot zusammen.
+/// Die Stundenpläne werden von `FETCH_WORKERS` Workern gleichzeitig abgerufen.
+///
+/// # Arguments
+/// * `client` - Untis Client mit dem die Elemente abgerufen werden sollen
+/// * `config` - Konfiguration mit den Elementtypen
+/// * `anonymizer` - Anonymizer mit der Datenschutz Richtlinie
+/// * `snapshot` - Snapshot dem die Unterrichtsstunden hinzugefügt werden
+fn fetch_lessons(client: &mut Client, config: &Config, anonymizer: &Anonymizer, snapshot: &mut Snapshot) -> Result<()> {
+    let started = Instant::now();
+    let (window_start, window_end) = snapshot.window();
+    let mut failures = Vec::new();
+
+    // Lädt alle Elemente der Elementtypen
+    let mut jobs = Vec::new();
+    for &kind in &config.element_types {
+        match element_ids(client, kind) {
+            Ok(ids) => jobs.extend(ids.into_iter().map(|id| Job { kind, id })),
+            Err(e) => {
+                error!("Elemente vom Typ {} konnten nicht geladen werden. {:#?}", kind, e);
+                failures.push(FetchFailure { element_type: kind.to_string(), element_id: None, error: ErrorKind::of(&e).into() });
+            }
+        }
+    }
+
+    let capture_start = Utc::now();
+    let results = fetch_timetables(client, config, &jobs, window_start, window_end);
+    snapshot.set_capture_times(capture_start, Utc::now());
+
+    // Die Unterrichtsstunden werden in der Reihenfolge der Elemente hinzugefügt, damit der Snapshot nicht davon abhängt,
+    // welcher Worker zuerst fertig war
+    let mut succeeded = 0;
+    for (job, result) in jobs.iter().zip(results) {
+        match result {
+            Ok(lessons) => {
+                succeeded += 1;
+                for mut lesson in lessons {
+                    // Pseudonymisiere die Lehrernamen und wende die Richtlinie auf die übrigen Felder an.
+                    // Ursprünglich eingeplante Lehrer werden genauso pseudonymisiert, damit sie sich zuordnen lassen.
+                    anonymizer.apply(&mut lesson);
+                    snapshot.add_lesson(lesson)
+                }
+            }
+            // Bei Lehrern wird nur die Id geloggt, damit keine Namen in den Logs landen
+            Err(e) => {
+                error!("Stundenplan für {} mit der Id {} konnte nicht geladen werden. {:#?}", job.kind, job.id, e);
+                failures.push(FetchFailure {
+                    element_type: job.kind.to_string(),
+                    // Untis Ids von Lehrern werden nicht im Snapshot gespeichert
+                    element_id: (job.kind != ElementKind::Teacher).then_some(job.id),
+                    error: ErrorKind::of(&e).into(),
+                });
+            }
+        }
+    }
+
+    snapshot.set_run_metadata(RunMetadata {
+        attempted: jobs.len(),
+        succeeded,
+        failures,
+        duration_ms: started.elapsed().as_millis() as u64,
+        scraper_version: env!("CARGO_PKG_VERSION").to_string(),
+        host: host_name(),
+        // Nur der Failover Server prüft den Status des Hauptservers
+        failover: config.state_file_check.is_some(),
+    });
+    if snapshot.is_complete() == Some(false) {
+        warn!("Der Snapshot ist unvollständig, {} von {} Stundenplänen wurden abgerufen.", succeeded, jobs.len());
+    }
+
+    Ok(())
+}
+
+/// Gibt den Namen des Rechners zurück
+fn host_name() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .unwrap_or_else(|| "unbekannt".to_string())
+}
+
+/// Ruft den Stundenplan eines Elements für den Zeitraum ab
+///
+/// # Arguments
+/// * `client` - Client mit dem der Stundenplan abgerufen wird
+/// * `job` - Element dessen Stundenplan abgerufen wird
+/// * `window_start` - Erster Tag des Zeitraums
+/// * `window_end` - Letzter Tag des Zeitraums
+fn fetch_timetable(client: &mut Client, job: Job, window_start: NaiveDate, window_end: NaiveDate) -> Result<Vec<Lesson>> {
+    trace!("Lade Stundenplan für {} mit der Id: {}", job.kind, job.id);
+    client
+        .call("Stundenplan", move |client| {
+            client.timetable_between(&job.id, &untis_element_type(job.kind), &Date(window_start), &Date(window_end))
+        })
+        .map(|lessons| lessons.iter().map(Lesson::from).collect())
+}
+
+/// Ruft die Stundenpläne mit `FETCH_WORKERS` Workern gleichzeitig ab. Jeder Worker nimmt sich das nächste Element
+/// und verwendet die Sitzung eines Workers von `client`. Mit nur einem Worker wird `client` selbst verwendet.
+///
+/// # Arguments
+/// * `client` - Client des Durchlaufs
+/// * `config` - Konfiguration mit der Anzahl der Worker
+/// * `jobs` - Elemente deren Stundenpläne abgerufen werden
+/// * `window_start` - Erster Tag des Zeitraums
+/// * `window_end` - Letzter Tag des Zeitraums
+///
+/// # Returns
+/// * `Vec<Result<Vec<Lesson>>>` - Unterrichtsstunden je Element, in der Reihenfolge der Elemente
+fn fetch_timetables(
+    client: &mut Client,
+    config: &Config,
+    jobs: &[Job],
+    window_start: NaiveDate,
+    window_end: NaiveDate,
+) -> Vec<Result<Vec<Lesson>>> {
+    let workers = config.fetch_workers.min(jobs.len());
+    if workers <= 1 {
+        return jobs.iter().map(|&job| fetch_timetable(client, job, window_start, window_end)).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for worker in client.workers(workers) {
+            let (next, sender) = (&next, sender.clone());
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&job) = jobs.get(index) else {
+                    break;
+                };
+                // Der Empfänger existiert, bis alle Worker beendet sind
+                let _ = sender.send((index, fetch_timetable(worker, job, window_start, window_end)));
+            });
+        }
+    });
+    drop(sender);
+
+    let mut results: Vec<Result<Vec<Lesson>>> =
+        jobs.iter().map(|_| Err(anyhow::anyhow!("Der Stundenplan wurde nicht abgerufen"))).collect();
+    for (index, result) in receiver {
+        results[index] = result;
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, test_date};
+
+    /// Gibt ein Datum im November 2023 zurück, der 20.11.2023 ist ein Montag
+    fn november(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 11, day).unwrap()
+    }
+
+    #[test]
+    fn add_school_days_skips_weekends() {
+        assert_eq!(add_school_days(november(20), 0), november(20));
+        assert_eq!(add_school_days(november(20), 4), november(24));
+        assert_eq!(add_school_days(november(24), 1), november(27));
+        assert_eq!(add_school_days(november(20), -1), november(17));
+        assert_eq!(add_school_days(november(20), 5), november(27));
+        assert_eq!(add_school_days(november(22), -10), november(8));
+    }
+
+    #[test]
+    fn add_school_days_from_weekend() {
+        assert_eq!(add_school_days(november(18), 1), november(20));
+        assert_eq!(add_school_days(november(19), -1), november(17));
+        // Ohne Verschiebung bleibt auch ein Tag am Wochenende unverändert
+        assert_eq!(add_school_days(november(18), 0), november(18));
+    }
+
+    #[test]
+    fn fetch_window_counts_school_days() {
+        let mut config = test_config("");
+        assert_eq!(fetch_window(test_date(), &config), (test_date(), test_date()));
+
+        config.fetch_days_before = 2;
+        config.fetch_days_ahead = 5;
+        assert_eq!(fetch_window(test_date(), &config), (november(16), november(27)));
+    }
+}
@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// Status repräsentiert den Status des Programms
+pub enum State {
+    /// Das Programm wurde erfolgreich ausgeführt
+    SUCCESS,
+    /// Das Programm wurde mit einem Fehler beendet
+    ERROR(String),
+    /// Das Programm wurde gestartet und läuft noch
+    STARTED,
+    /// Der Durchlauf wurde übersprungen, weil kein Unterricht stattfindet, enthält den Grund (z.B. "Ferien Herbstferien").
+    /// Ältere Versionen kennen diesen Status nicht und brechen beim Lesen ab, daher müssen Failover Server vor dem
+    /// Hauptserver aktualisiert werden. Unbekannte Status werden seitdem geloggt und wie ein fehlender Status behandelt.
+    SKIPPED(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// ReportedState repräsentiert den Status des Programms der in der Status Datei gespeichert wird
+pub struct ReportedState {
+    /// Status des Programms
+    pub state: State,
+    /// Zeitpunkt zu dem der Status gesetzt wurde
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Aktualisiert den Status des Programms
+///
+/// # Arguments
+/// * `path` - Pfad an dem die Status Datei gespeichert werden soll
+/// * `state` - Status der gesetzt werden soll
+pub fn update_state(path: &str, state: State) -> Result<()> {
+    let state = ReportedState {
+        state,
+        timestamp: Utc::now(),
+    };
+    let state = serde_json::to_string(&state)?;
+    std::fs::write(path, state)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_state_format() {
+        // Das Format wird von den Failover Servern gelesen und darf sich nicht ändern
+        let state = ReportedState { state: State::SKIPPED("Wochenende".to_string()), timestamp: DateTime::UNIX_EPOCH };
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, r#"{"state":{"SKIPPED":"Wochenende"},"timestamp":"1970-01-01T00:00:00Z"}"#);
+        assert_eq!(serde_json::from_str::<ReportedState>(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn unknown_state_is_an_error() {
+        // Unbekannte Status führen zu einem Fehler, den `primary_has_run` loggt, statt abzubrechen
+        assert!(serde_json::from_str::<ReportedState>(r#"{"state":"PAUSED","timestamp":"1970-01-01T00:00:00Z"}"#).is_err());
+    }
+}
@@ -0,0 +1,882 @@
+//! Speicherformat der Tagesdateien.
+//!
+//! Jede Tagesdatei ist ein Snapshot Log: Nach einem Dateikopf folgt für jeden Snapshot ein eigener Eintrag,
+//! der nur angehängt und nie überschrieben wird.
+//!
+//! ```text
+//! Dateikopf (16 Byte): "SMSLOG\0\0" | Version (u32) | reserviert (u32)
+//! Eintrag:             Länge (u32) | CRC32 (u32) | Art (u32) | reserviert (u32) | rkyv Daten | Auffüllung auf 16 Byte
+//! ```
+//!
+//! Die Art eines Eintrags ist entweder ein Snapshot (1) oder eine Version der Stammdaten (2). Stammdaten werden nur angehängt,
+//! wenn ihre Version noch nicht in der Tagesdatei enthalten ist, und stehen immer vor dem ersten Snapshot der auf sie verweist.
+//!
+//! Beim Anhängen wird die Tagesdatei über eine Sperrdatei (`.D.bin.lock`) gesperrt, damit sich gleichzeitige Durchläufe
+//! (z.B. Haupt- und Failover Server auf demselben NFS Share) nicht gegenseitig ein halb geschriebenes Ende abschneiden.
+//!
+//! Alle Zahlen sind Little Endian. Da jeder Eintrag auf 16 Byte aufgefüllt wird, beginnen die rkyv Daten immer an einer
+//! ausgerichteten Position. Dateien im alten Format (Version 0), in denen das gesamte `ExportFile` am Stück gespeichert ist,
+//! werden weiterhin gelesen und beim nächsten Anhängen oder mit `migrate` in ein Snapshot Log überführt. Die Strukturen
+//! des alten Formats liegen in `data::migrate`.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use log::warn;
+use rkyv::{
+    check_archived_root, de::deserializers::SharedDeserializeMap, validation::validators::DefaultValidator, AlignedVec, Archive,
+    CheckBytes, Deserialize,
+};
+
+use crate::{
+    data::{ExportFile, Snapshot},
+    master_data::MasterData,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Kennung am Anfang jedes Snapshot Logs
+pub const MAGIC: [u8; 8] = *b"SMSLOG\0\0";
+/// Version des Formats der Einträge
+pub const FORMAT_VERSION: u32 = 6;
+
+/// Länge des Dateikopfs
+pub(crate) const HEADER_LEN: usize = 16;
+/// Länge des Kopfs eines Eintrags
+pub(crate) const RECORD_HEADER_LEN: usize = 16;
+/// Ausrichtung der rkyv Daten innerhalb der Datei
+const ALIGNMENT: usize = 16;
+/// Art des Eintrags: Snapshot
+pub(crate) const RECORD_SNAPSHOT: u32 = 1;
+/// Art des Eintrags: Stammdaten
+pub(crate) const RECORD_MASTER_DATA: u32 = 2;
+/// Höchste Länge der rkyv Daten eines Eintrags. Längere Einträge gelten als beschädigt, damit ein beschädigter Kopf
+/// nicht zu einer Speicheranforderung von bis zu 4 GiB führt.
+const MAX_RECORD_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Debug)]
+/// 'ArchiveError' repräsentiert die Fehler, die beim Lesen einer Exportierten Datei auftreten können.
+pub enum ArchiveError {
+    /// Die Datei konnte nicht gelesen werden
+    Io(io::Error),
+    /// Die Datei ist beschädigt und konnte nicht validiert werden
+    Corrupt {
+        /// Pfad der beschädigten Datei
+        path: String,
+        /// Grund warum die Validierung fehlgeschlagen ist
+        reason: String,
+    },
+    /// Die Datei wurde mit einer anderen Version des Formats geschrieben
+    UnsupportedVersion {
+        /// Pfad der Datei
+        path: String,
+        /// Version mit der die Datei geschrieben wurde
+        version: u32,
+    },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "Datei konnte nicht gelesen werden: {}", e),
+            ArchiveError::Corrupt { path, reason } => write!(f, "Datei \"{}\" ist beschädigt: {}", path, reason),
+            ArchiveError::UnsupportedVersion { path, version: 0 } => write!(
+                f,
+                "Datei \"{}\" liegt im alten Format vor und muss mit `migrate` in Version {} überführt werden",
+                path, FORMAT_VERSION
+            ),
+            ArchiveError::UnsupportedVersion { path, version } => write!(
+                f,
+                "Datei \"{}\" hat die Version {}, unterstützt wird Version {}",
+                path, version, FORMAT_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(value: io::Error) -> Self {
+        ArchiveError::Io(value)
+    }
+}
+
+/// Kopf eines Eintrags im Snapshot Log
+pub(crate) struct RecordHeader {
+    /// Länge der rkyv Daten ohne Auffüllung
+    pub(crate) len: usize,
+    /// CRC32 Prüfsumme der rkyv Daten
+    pub(crate) checksum: u32,
+    /// Art des Eintrags
+    pub(crate) kind: u32,
+}
+
+impl RecordHeader {
+    /// Liest den Kopf eines Eintrags aus den Bytes
+    pub(crate) fn parse(bytes: &[u8; RECORD_HEADER_LEN]) -> Self {
+        let field = |index: usize| u32::from_le_bytes([bytes[index], bytes[index + 1], bytes[index + 2], bytes[index + 3]]);
+        Self { len: field(0) as usize, checksum: field(4), kind: field(8) }
+    }
+
+    /// Länge der rkyv Daten inklusive Auffüllung
+    pub(crate) fn padded_len(&self) -> usize {
+        padded(self.len)
+    }
+}
+
+/// Gibt die auf die Ausrichtung aufgefüllte Länge zurück
+fn padded(len: usize) -> usize {
+    len.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+/// Erstellt den Dateikopf eines Snapshot Logs
+fn encode_header() -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&MAGIC);
+    header[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header
+}
+
+/// Erstellt einen vollständigen Eintrag inklusive Kopf und Auffüllung
+///
+/// # Arguments
+/// * `kind` - Art des Eintrags
+/// * `payload` - rkyv Daten des Eintrags
+fn encode_record(kind: u32, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + padded(payload.len()));
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    record.extend_from_slice(&kind.to_le_bytes());
+    record.extend_from_slice(&0u32.to_le_bytes());
+    record.extend_from_slice(payload);
+    record.resize(RECORD_HEADER_LEN + padded(payload.len()), 0);
+    record
+}
+
+/// Serialisiert einen Snapshot als Eintrag des Snapshot Logs
+fn encode_snapshot(snapshot: &Snapshot) -> Result<Vec<u8>> {
+    let payload = rkyv::to_bytes::<_, 1024>(snapshot)
+        .map_err(|e| anyhow::anyhow!("Snapshot konnte nicht serialisiert werden: {:?}", e))?;
+    Ok(encode_record(RECORD_SNAPSHOT, &payload))
+}
+
+/// Serialisiert Stammdaten als Eintrag des Snapshot Logs
+fn encode_master_data(master_data: &MasterData) -> Result<Vec<u8>> {
+    let payload = rkyv::to_bytes::<_, 1024>(master_data)
+        .map_err(|e| anyhow::anyhow!("Stammdaten konnten nicht serialisiert werden: {:?}", e))?;
+    Ok(encode_record(RECORD_MASTER_DATA, &payload))
+}
+
+/// Validiert und deserialisiert die rkyv Daten eines Eintrags
+///
+/// # Arguments
+/// * `path` - Pfad der Datei, wird für Fehlermeldungen benötigt
+/// * `offset` - Position des Eintrags in der Datei, wird für Fehlermeldungen benötigt
+/// * `payload` - Ausgerichtete rkyv Daten
+fn decode<T>(path: &str, offset: u64, payload: &[u8]) -> std::result::Result<T, ArchiveError>
+where
+    T: Archive,
+    T::Archived: for<'a> CheckBytes<DefaultValidator<'a>> + Deserialize<T, SharedDeserializeMap>,
+{
+    let corrupt = |reason: String| ArchiveError::Corrupt { path: path.to_string(), reason: format!("Eintrag bei Offset {}: {}", offset, reason) };
+    let archived = check_archived_root::<T>(payload).map_err(|e| corrupt(e.to_string()))?;
+    archived.deserialize(&mut SharedDeserializeMap::default()).map_err(|e| corrupt(format!("{:?}", e)))
+}
+
+/// Liest so viele Bytes wie möglich in den Buffer. Im Gegensatz zu `read_exact` wird am Ende der Datei kein Fehler zurückgegeben.
+///
+/// # Returns
+/// * `usize` - Anzahl der gelesenen Bytes
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Gibt den Pfad der Tagesdatei für das angegebene Datum zurück
+///
+/// # Arguments
+/// * `storage_path` - Pfad an dem die Daten gespeichert werden
+/// * `date` - Datum der Tagesdatei
+///
+/// # Returns
+/// * `PathBuf` - Pfad im Format `STORAGE_PATH/YYYY/M/D.bin`
+pub fn day_path(storage_path: &str, date: NaiveDate) -> PathBuf {
+    PathBuf::from(format!("{}/{}/{}/{}.bin", storage_path, date.year(), date.month(), date.day()))
+}
+
+/// Gibt die Pfade aller vorhandenen Tagesdateien zwischen den angegebenen Daten zurück. Tage ohne Datei werden übersprungen.
+///
+/// # Arguments
+/// * `storage_path` - Pfad an dem die Daten gespeichert werden
+/// * `from` - Erster Tag (inklusive)
+/// * `to` - Letzter Tag (inklusive)
+pub fn day_paths_between(storage_path: &str, from: NaiveDate, to: NaiveDate) -> Vec<(NaiveDate, PathBuf)> {
+    from.iter_days()
+        .take_while(|date| *date <= to)
+        .map(|date| (date, day_path(storage_path, date)))
+        .filter(|(_, path)| path.exists())
+        .collect()
+}
+
+/// 'SnapshotReader' liest die Snapshots einer Tagesdatei nacheinander.
+/// Snapshot Logs werden Eintrag für Eintrag gelesen, Dateien im alten Format werden vollständig geladen.
+pub enum SnapshotReader {
+    /// Tagesdatei im Log Format
+    Log {
+        /// Pfad der Datei, wird für Fehlermeldungen benötigt
+        path: String,
+        /// Reader der Datei
+        reader: BufReader<File>,
+        /// Position des nächsten Eintrags
+        offset: u64,
+        /// Gibt an ob das Ende der Datei oder ein Fehler erreicht wurde
+        done: bool,
+    },
+    /// Tagesdatei im alten Format
+    Legacy(std::vec::IntoIter<Snapshot>),
+}
+
+impl SnapshotReader {
+    /// Öffnet eine Tagesdatei
+    ///
+    /// # Arguments
+    /// * `path` - Pfad der Tagesdatei
+    pub fn open(path: &Path) -> std::result::Result<Self, ArchiveError> {
+        match open_log(path)? {
+            Some(reader) => Ok(Self::Log { path: path.display().to_string(), reader, offset: HEADER_LEN as u64, done: false }),
+            None => {
+                let export_file = ExportFile::read_legacy(path)?;
+                Ok(Self::Legacy(export_file.into_snapshots().into_iter()))
+            }
+        }
+    }
+}
+
+/// Öffnet ein Snapshot Log und prüft den Dateikopf
+///
+/// # Returns
+/// * `Some(BufReader<File>)` - Reader der auf den ersten Eintrag zeigt
+/// * `None` - Wenn die Datei im alten Format vorliegt
+fn open_log(path: &Path) -> std::result::Result<Option<BufReader<File>>, ArchiveError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; HEADER_LEN];
+    let read = read_full(&mut reader, &mut header)?;
+
+    // Dateien ohne Kennung wurden im alten Format geschrieben
+    if read < MAGIC.len() || header[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+
+    let path = path.display().to_string();
+    if read < HEADER_LEN {
+        return Err(ArchiveError::Corrupt { path, reason: "Dateikopf ist unvollständig".to_string() });
+    }
+    let version = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if version != FORMAT_VERSION {
+        return Err(ArchiveError::UnsupportedVersion { path, version });
+    }
+    Ok(Some(reader))
+}
+
+/// Liest alle Versionen der Stammdaten aus einer Tagesdatei. Dateien im alten Format enthalten keine Stammdaten.
+///
+/// # Arguments
+/// * `path` - Pfad der Tagesdatei
+///
+/// # Returns
+/// * `Vec<MasterData>` - Stammdaten in der Reihenfolge in der sie gespeichert wurden
+pub fn read_master_data(path: &Path) -> std::result::Result<Vec<MasterData>, ArchiveError> {
+    let Some(mut reader) = open_log(path)? else {
+        return Ok(Vec::new());
+    };
+    let display_path = path.display().to_string();
+    let mut master_data = Vec::new();
+    let mut offset = HEADER_LEN as u64;
+    while let Some((header, payload)) = read_record(&mut reader, &display_path, offset)? {
+        if header.kind == RECORD_MASTER_DATA {
+            master_data.push(decode::<MasterData>(&display_path, offset, &payload)?);
+        }
+        offset += (RECORD_HEADER_LEN + header.padded_len()) as u64;
+    }
+    Ok(master_data)
+}
+
+impl Iterator for SnapshotReader {
+    type Item = std::result::Result<Snapshot, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, reader, offset, done) = match self {
+            Self::Legacy(snapshots) => return snapshots.next().map(Ok),
+            Self::Log { path, reader, offset, done } => (path, reader, offset, done),
+        };
+
+        while !*done {
+            let record_offset = *offset;
+            let result = match read_record(reader, path, record_offset) {
+                Ok(Some((header, payload))) => {
+                    *offset += (RECORD_HEADER_LEN + header.padded_len()) as u64;
+                    // Unbekannte Einträge werden übersprungen, damit ältere Versionen neuere Dateien weiterhin lesen können
+                    if header.kind != RECORD_SNAPSHOT {
+                        continue;
+                    }
+                    decode::<Snapshot>(path, record_offset, &payload)
+                }
+                Ok(None) => {
+                    *done = true;
+                    return None;
+                }
+                Err(e) => Err(e),
+            };
+            // Nach einem Fehler wird nicht weiter gelesen, da die Position der folgenden Einträge unbekannt ist
+            if result.is_err() {
+                *done = true;
+            }
+            return Some(result);
+        }
+        None
+    }
+}
+
+/// Liest den nächsten Eintrag aus dem Snapshot Log und prüft seine Prüfsumme
+///
+/// # Arguments
+/// * `reader` - Reader der auf den Anfang des Eintrags zeigt
+/// * `path` - Pfad der Datei, wird für Fehlermeldungen benötigt
+/// * `offset` - Position des Eintrags in der Datei, wird für Fehlermeldungen benötigt
+///
+/// # Returns
+/// * `Some((RecordHeader, AlignedVec))` - Kopf und ausgerichtete rkyv Daten des Eintrags
+/// * `None` - Wenn das Ende der Datei erreicht wurde
+fn read_record(reader: &mut impl Read, path: &str, offset: u64) -> std::result::Result<Option<(RecordHeader, AlignedVec)>, ArchiveError> {
+    let corrupt = |reason: &str| ArchiveError::Corrupt { path: path.to_string(), reason: format!("Eintrag bei Offset {} {}", offset, reason) };
+
+    // Liest den Kopf des Eintrags
+    let mut bytes = [0u8; RECORD_HEADER_LEN];
+    match read_full(reader, &mut bytes)? {
+        0 => return Ok(None),
+        RECORD_HEADER_LEN => {}
+        _ => return Err(corrupt("ist unvollständig")),
+    }
+    let header = RecordHeader::parse(&bytes);
+    if header.len > MAX_RECORD_LEN {
+        return Err(corrupt(&format!("ist mit {} Bytes zu lang", header.len)));
+    }
+
+    // Liest die Daten des Eintrags inklusive Auffüllung. Der Buffer wächst nur mit den tatsächlich gelesenen Daten,
+    // damit eine zu große Länge am Ende der Datei nicht vorab Speicher anfordert
+    let mut payload = Vec::new();
+    reader.by_ref().take(header.padded_len() as u64).read_to_end(&mut payload)?;
+    if payload.len() < header.padded_len() {
+        return Err(corrupt("ist unvollständig"));
+    }
+    let payload = &payload[..header.len];
+    if crc32fast::hash(payload) != header.checksum {
+        return Err(corrupt("hat eine falsche Prüfsumme"));
+    }
+
+    // Kopiert die Daten in einen ausgerichteten Buffer
+    let mut aligned = AlignedVec::with_capacity(payload.len());
+    aligned.extend_from_slice(payload);
+    Ok(Some((header, aligned)))
+}
+
+/// Hängt einen Snapshot an die Tagesdatei des angegebenen Datums an.
+/// Existiert die Datei noch nicht, wird sie erstellt. Dateien im alten Format werden vorher in das Log Format überführt.
+///
+/// # Arguments
+/// * `storage_path` - Pfad an dem die Daten gespeichert werden
+/// * `date` - Datum der Tagesdatei
+/// * `snapshot` - Snapshot der angehängt werden soll
+/// * `master_data` - Stammdaten auf die der Snapshot verweist, werden nur gespeichert wenn ihre Version noch nicht in der Datei enthalten ist
+pub fn append_snapshot(storage_path: &str, date: NaiveDate, snapshot: &Snapshot, master_data: Option<&MasterData>) -> Result<()> {
+    let path = day_path(storage_path, date);
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    // Die Sperre wird bis nach dem Anhängen gehalten, damit kein anderer Durchlauf ein halb geschriebenes Ende sieht
+    let _lock = lock(&path)?;
+    let master_data_versions = prepare_log(&path)?;
+
+    // Die Einträge werden mit einem einzigen Schreibvorgang angehängt, damit sich gleichzeitige Durchläufe nicht vermischen
+    let mut record = Vec::new();
+    if let Some(master_data) = master_data {
+        if !master_data_versions.contains(master_data.version()) {
+            record.extend_from_slice(&encode_master_data(master_data)?);
+        }
+    }
+    record.extend_from_slice(&encode_snapshot(snapshot)?);
+    let mut file = OpenOptions::new().append(true).open(&path)?;
+    file.write_all(&record)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Schreibt die Stammdaten und Snapshots atomar als neues Snapshot Log an den angegebenen Pfad.
+/// Eine bereits vorhandene Datei wird ersetzt.
+///
+/// # Arguments
+/// * `path` - Pfad an dem die Datei gespeichert werden soll
+/// * `master_data` - Stammdaten die vor den Snapshots in das Log geschrieben werden sollen
+/// * `snapshots` - Snapshots die in das Log geschrieben werden sollen
+pub fn write_log(path: &Path, master_data: &[MasterData], snapshots: &[Snapshot]) -> Result<()> {
+    let mut data = encode_header().to_vec();
+    for master_data in master_data {
+        data.extend_from_slice(&encode_master_data(master_data)?);
+    }
+    for snapshot in snapshots {
+        data.extend_from_slice(&encode_snapshot(snapshot)?);
+    }
+    write_atomic(path, &data)
+}
+
+/// Sperrt eine Tagesdatei für andere Durchläufe, bis die zurückgegebene Datei geschlossen wird. Die Sperre liegt auf einer
+/// eigenen Datei neben der Tagesdatei, da die Tagesdatei beim Überführen durch eine neue Datei ersetzt wird.
+/// Wird auch für den Tresor verwendet, an den ebenfalls mehrere Durchläufe anhängen.
+///
+/// # Arguments
+/// * `path` - Pfad der Tagesdatei
+pub(crate) fn lock(path: &Path) -> Result<File> {
+    let lock_path = sibling_path(path, "lock")?;
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(lock_path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Gibt den Pfad einer versteckten Hilfsdatei im selben Ordner zurück, z.B. `.16.bin.lock`
+fn sibling_path(path: &Path, suffix: &str) -> Result<PathBuf> {
+    let folder = path.parent().unwrap_or(Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Pfad \"{}\" enthält keinen Dateinamen", path.display()))?;
+    Ok(folder.join(format!(".{}.{}", file_name.to_string_lossy(), suffix)))
+}
+
+/// Stellt sicher, dass am angegebenen Pfad ein gültiges Snapshot Log liegt, an das angehängt werden kann.
+/// Die Tagesdatei muss vorher mit [`lock`] gesperrt werden.
+/// * Existiert die Datei nicht, wird ein leeres Log erstellt.
+/// * Liegt die Datei im alten Format vor, wird sie in ein Snapshot Log überführt.
+/// * Ist der letzte Eintrag unvollständig oder hat er eine falsche Prüfsumme (z.B. durch einen Absturz beim Anhängen),
+///   wird er beiseite gelegt und abgeschnitten.
+/// * Ist ein Eintrag vor dem Ende beschädigt, wird die gesamte Datei beiseite gelegt und ein leeres Log erstellt.
+///
+/// Lesefehler werden zurückgegeben, ohne die Datei zu verändern.
+///
+/// # Returns
+/// * `HashSet<String>` - Versionen der Stammdaten, die im Log gespeichert sind. Sie werden beim Prüfen der Einträge gesammelt,
+///   damit das Log beim Anhängen nicht ein zweites Mal gelesen werden muss.
+fn prepare_log(path: &Path) -> Result<HashSet<String>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            create_log(path)?;
+            return Ok(HashSet::new());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut header = [0u8; HEADER_LEN];
+    let read = read_full(&mut file, &mut header)?;
+    if read < MAGIC.len() || header[..MAGIC.len()] != MAGIC {
+        drop(file);
+        // Dateien im alten Format enthalten keine Stammdaten
+        migrate_legacy(path)?;
+        return Ok(HashSet::new());
+    }
+    if read < HEADER_LEN {
+        drop(file);
+        let quarantine_path = quarantine(path)?;
+        warn!("Dateikopf von \"{}\" ist unvollständig, die Datei wurde nach \"{}\" verschoben.", path.display(), quarantine_path.display());
+        create_log(path)?;
+        return Ok(HashSet::new());
+    }
+    let version = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+    if version != FORMAT_VERSION {
+        return Err(ArchiveError::UnsupportedVersion { path: path.display().to_string(), version }.into());
+    }
+
+    // Sucht das Ende des letzten vollständigen Eintrags
+    let display_path = path.display().to_string();
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut valid_len = HEADER_LEN as u64;
+    let mut master_data_versions = HashSet::new();
+    let damage = loop {
+        match read_record(&mut reader, &display_path, valid_len) {
+            Ok(Some((record, payload))) => {
+                // Stammdaten, die nicht gelesen werden können, gelten als nicht gespeichert und werden erneut angehängt
+                if record.kind == RECORD_MASTER_DATA {
+                    if let Ok(master_data) = check_archived_root::<MasterData>(&payload) {
+                        master_data_versions.insert(master_data.version().to_string());
+                    }
+                }
+                valid_len += (RECORD_HEADER_LEN + record.padded_len()) as u64;
+            }
+            Ok(None) => break None,
+            Err(ArchiveError::Io(e)) => return Err(e.into()),
+            Err(e) => break Some(e),
+        }
+    };
+
+    if let Some(damage) = damage {
+        let mut file = reader.into_inner();
+        if !is_torn_tail(&mut file, valid_len, file_len)? {
+            drop(file);
+            let quarantine_path = quarantine(path)?;
+            warn!("{}. Die Datei wurde nach \"{}\" verschoben.", damage, quarantine_path.display());
+            create_log(path)?;
+            return Ok(HashSet::new());
+        }
+
+        // Sichert das unvollständige Ende und schneidet es ab, damit neue Einträge wieder gelesen werden können
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(valid_len))?;
+        file.read_to_end(&mut tail)?;
+        let (quarantine_path, mut quarantine_file) = create_quarantine_file(path)?;
+        quarantine_file.write_all(&tail)?;
+        quarantine_file.sync_all()?;
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(valid_len)?;
+        file.sync_all()?;
+        warn!(
+            "Die letzten {} Bytes von \"{}\" sind unvollständig und wurden nach \"{}\" verschoben.",
+            file_len - valid_len,
+            path.display(),
+            quarantine_path.display()
+        );
+    }
+    Ok(master_data_versions)
+}
+
+/// Prüft ob der beschädigte Eintrag an der angegebenen Position das Ende der Datei ist, also beim Anhängen nicht
+/// vollständig geschrieben wurde. Das ist der Fall, wenn der Kopf oder die Daten über das Ende der Datei hinausgehen
+/// oder der Eintrag genau am Ende der Datei endet. Nur ein solches Ende darf abgeschnitten werden.
+///
+/// # Arguments
+/// * `file` - Geöffnete Tagesdatei
+/// * `offset` - Position des beschädigten Eintrags
+/// * `file_len` - Länge der Datei
+fn is_torn_tail(file: &mut File, offset: u64, file_len: u64) -> io::Result<bool> {
+    if file_len - offset < RECORD_HEADER_LEN as u64 {
+        return Ok(true);
+    }
+    let mut bytes = [0u8; RECORD_HEADER_LEN];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    let header = RecordHeader::parse(&bytes);
+    Ok(header.len <= MAX_RECORD_LEN && offset + (RECORD_HEADER_LEN + header.padded_len()) as u64 >= file_len)
+}
+
+/// Erstellt ein leeres Snapshot Log. Wurde die Datei in der Zwischenzeit von einem anderen Durchlauf erstellt, wird diese verwendet.
+fn create_log(path: &Path) -> Result<()> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            file.write_all(&encode_header())?;
+            file.sync_all()?;
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Überführt eine Tagesdatei im alten Format in das Log Format.
+/// Ist die Datei beschädigt, wird sie beiseite gelegt und ein leeres Log erstellt.
+fn migrate_legacy(path: &Path) -> Result<()> {
+    match ExportFile::read_legacy(path) {
+        Ok(export_file) => write_log(path, &[], &export_file.into_snapshots()),
+        Err(ArchiveError::Corrupt { reason, .. }) => {
+            let quarantine_path = quarantine(path)?;
+            warn!("Datei \"{}\" ist beschädigt ({}) und wurde nach \"{}\" verschoben.", path.display(), reason, quarantine_path.display());
+            create_log(path)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Überführt eine Tagesdatei im alten Format in die aktuelle Version
+///
+/// # Arguments
+/// * `path` - Pfad der Tagesdatei
+///
+/// # Returns
+/// * `Some(u32)` - Version aus der die Datei überführt wurde
+/// * `None` - Wenn die Datei bereits in der aktuellen Version vorliegt
+pub fn migrate(path: &Path) -> Result<Option<u32>> {
+    let _lock = lock(path)?;
+    if open_log(path)?.is_some() {
+        return Ok(None);
+    }
+    prepare_log(path)?;
+    Ok(Some(0))
+}
+
+/// Legt eine neue, leere `.corrupt` Datei neben der angegebenen Datei an. Datum und Uhrzeit werden angehängt und bei Bedarf
+/// um einen Zähler ergänzt, damit keine frühere `.corrupt` Datei überschrieben wird.
+///
+/// # Returns
+/// * `(PathBuf, File)` - Pfad und geöffnete `.corrupt` Datei
+fn create_quarantine_file(path: &Path) -> Result<(PathBuf, File)> {
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3f");
+    let mut counter = 0;
+    loop {
+        let quarantine_path = match counter {
+            0 => PathBuf::from(format!("{}.{}.corrupt", path.display(), timestamp)),
+            _ => PathBuf::from(format!("{}.{}-{}.corrupt", path.display(), timestamp, counter)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&quarantine_path) {
+            Ok(file) => return Ok((quarantine_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Verschiebt eine beschädigte Datei in eine `.corrupt` Datei, damit sie später untersucht werden kann.
+///
+/// # Arguments
+/// * `path` - Pfad der beschädigten Datei
+///
+/// # Returns
+/// * `PathBuf` - Pfad unter dem die Datei nun liegt
+pub fn quarantine(path: &Path) -> Result<PathBuf> {
+    // Die leere `.corrupt` Datei reserviert den Namen und wird durch das Umbenennen ersetzt
+    let (quarantine_path, _) = create_quarantine_file(path)?;
+    fs::rename(path, &quarantine_path)?;
+    Ok(quarantine_path)
+}
+
+/// Schreibt die Daten atomar an den angegebenen Pfad.
+/// Die Daten werden zuerst in eine temporäre Datei im selben Ordner geschrieben, auf den Datenträger synchronisiert
+/// und anschließend über die Zieldatei umbenannt. Stürzt das Programm während des Schreibens ab, bleibt die alte Datei erhalten.
+///
+/// # Arguments
+/// * `path` - Pfad an dem die Datei gespeichert werden soll
+/// * `data` - Daten die gespeichert werden sollen
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let folder = path.parent().unwrap_or(Path::new("."));
+
+    // Die temporäre Datei muss im selben Ordner liegen, da das Umbenennen nur innerhalb eines Dateisystems atomar ist
+    let tmp_path = sibling_path(path, &format!("{}.tmp", std::process::id()))?;
+
+    // Schreibt die Daten in die temporäre Datei und synchronisiert sie auf den Datenträger
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        // Entfernt die unvollständige temporäre Datei, der Fehler beim Entfernen ist hier nicht relevant
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // Ersetzt die Zieldatei durch die temporäre Datei
+    fs::rename(&tmp_path, path)?;
+
+    // Synchronisiert den Ordner, damit auch das Umbenennen auf dem Datenträger (bzw. dem NFS Server) landet
+    sync_dir(folder)?;
+    Ok(())
+}
+
+/// Synchronisiert einen Ordner auf den Datenträger
+///
+/// # Arguments
+/// * `folder` - Ordner der synchronisiert werden soll
+#[cfg(unix)]
+fn sync_dir(folder: &Path) -> Result<()> {
+    File::open(folder)?.sync_all()?;
+    Ok(())
+}
+
+/// Unter Windows können Ordner nicht geöffnet und synchronisiert werden.
+#[cfg(not(unix))]
+fn sync_dir(_folder: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{baseline_day_file, lesson, temp_dir, test_date};
+
+    /// Erstellt einen Snapshot mit der angegebenen Anzahl an Unterrichtsstunden
+    fn snapshot(lessons: usize) -> Snapshot {
+        let mut snapshot = Snapshot::new(test_date(), test_date());
+        for id in 0..lessons {
+            snapshot.add_lesson(lesson(id, "10a", 8 + id as u32));
+        }
+        snapshot
+    }
+
+    /// Liest alle Snapshots einer Tagesdatei und gibt die Anzahl ihrer Unterrichtsstunden zurück
+    fn lesson_counts(path: &Path) -> Vec<usize> {
+        SnapshotReader::open(path).unwrap().map(|snapshot| snapshot.unwrap().lessons().len()).collect()
+    }
+
+    /// Gibt die `.corrupt` Dateien in einem Ordner zurück
+    fn corrupt_files(folder: &Path) -> Vec<PathBuf> {
+        fs::read_dir(folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "corrupt"))
+            .collect()
+    }
+
+    /// Hängt zwei Snapshots mit einer und zwei Unterrichtsstunden an eine neue Tagesdatei an
+    ///
+    /// # Returns
+    /// * `(String, PathBuf)` - Pfad des Speichers und der Tagesdatei
+    fn log_with_two_snapshots(name: &str) -> (String, PathBuf) {
+        let storage_path = temp_dir(name).display().to_string();
+        append_snapshot(&storage_path, test_date(), &snapshot(1), None).unwrap();
+        append_snapshot(&storage_path, test_date(), &snapshot(2), None).unwrap();
+        let path = day_path(&storage_path, test_date());
+        (storage_path, path)
+    }
+
+    #[test]
+    fn appended_snapshots_are_read_back() {
+        let (_, path) = log_with_two_snapshots("roundtrip");
+        assert_eq!(lesson_counts(&path), [1, 2]);
+
+        let snapshots = ExportFile::read(&path).unwrap().into_snapshots();
+        assert_eq!(snapshots[1].lessons()[1], lesson(1, "10a", 9));
+        assert_eq!(snapshots[1].window(), (test_date(), test_date()));
+    }
+
+    #[test]
+    fn torn_tail_is_quarantined_and_cut() {
+        let (storage_path, path) = log_with_two_snapshots("torn");
+        let file_len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(file_len - 5).unwrap();
+        let results: Vec<_> = SnapshotReader::open(&path).unwrap().collect();
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(ArchiveError::Corrupt { .. })));
+
+        append_snapshot(&storage_path, test_date(), &snapshot(3), None).unwrap();
+        assert_eq!(lesson_counts(&path), [1, 3]);
+        let quarantined = corrupt_files(path.parent().unwrap());
+        assert_eq!(quarantined.len(), 1);
+        let second_record = encode_snapshot(&snapshot(2)).unwrap();
+        assert_eq!(fs::read(&quarantined[0]).unwrap(), second_record[..second_record.len() - 5]);
+    }
+
+    #[test]
+    fn wrong_checksum_of_last_record_is_cut() {
+        let (storage_path, path) = log_with_two_snapshots("crc-tail");
+        let first_len = encode_snapshot(&snapshot(1)).unwrap().len();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN + first_len + RECORD_HEADER_LEN] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        append_snapshot(&storage_path, test_date(), &snapshot(3), None).unwrap();
+        assert_eq!(lesson_counts(&path), [1, 3]);
+        assert_eq!(corrupt_files(path.parent().unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn wrong_checksum_before_the_end_quarantines_the_file() {
+        let (storage_path, path) = log_with_two_snapshots("crc-middle");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN + RECORD_HEADER_LEN] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        append_snapshot(&storage_path, test_date(), &snapshot(3), None).unwrap();
+        assert_eq!(lesson_counts(&path), [3]);
+        let quarantined = corrupt_files(path.parent().unwrap());
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(fs::read(&quarantined[0]).unwrap(), bytes);
+    }
+
+    #[test]
+    fn oversized_record_length_is_corrupt() {
+        let (_, path) = log_with_two_snapshots("oversized");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        file.write_all(&header).unwrap();
+
+        let results: Vec<_> = SnapshotReader::open(&path).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(&results[2], Err(ArchiveError::Corrupt { reason, .. }) if reason.contains("zu lang")));
+    }
+
+    #[test]
+    fn baseline_day_file_is_migrated_on_append() {
+        let storage_path = temp_dir("migrate").display().to_string();
+        let path = day_path(&storage_path, test_date());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, baseline_day_file()).unwrap();
+
+        append_snapshot(&storage_path, test_date(), &snapshot(1), None).unwrap();
+        assert!(open_log(&path).unwrap().is_some());
+        assert_eq!(lesson_counts(&path), [2, 1]);
+        assert!(corrupt_files(path.parent().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn master_data_is_stored_once_per_version() {
+        let storage_path = temp_dir("master-data-once").display().to_string();
+        let master_data = MasterData::new(None, Vec::new(), Vec::new(), Vec::new(), vec!["4f2a".to_string()], Vec::new(), Vec::new()).unwrap();
+        let changed = MasterData::new(None, Vec::new(), Vec::new(), Vec::new(), vec!["9c1e".to_string()], Vec::new(), Vec::new()).unwrap();
+        append_snapshot(&storage_path, test_date(), &snapshot(1), Some(&master_data)).unwrap();
+        append_snapshot(&storage_path, test_date(), &snapshot(1), Some(&master_data)).unwrap();
+        append_snapshot(&storage_path, test_date(), &snapshot(1), Some(&changed)).unwrap();
+
+        let path = day_path(&storage_path, test_date());
+        let versions: Vec<_> = read_master_data(&path).unwrap().iter().map(|master_data| master_data.version().to_string()).collect();
+        assert_eq!(versions, [master_data.version(), changed.version()]);
+        assert_eq!(lesson_counts(&path), [1, 1, 1]);
+    }
+
+    #[test]
+    fn unreadable_master_data_is_reported_by_the_archive_reader() {
+        let (_, path) = log_with_two_snapshots("master-data");
+        // Die Prüfsumme stimmt, die rkyv Daten sind aber zu kurz für Stammdaten
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&encode_record(RECORD_MASTER_DATA, &[0xff; 16])).unwrap();
+
+        let reader = crate::reader::ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.snapshots().filter(Result::is_ok).count(), 2);
+        let results: Vec<_> = reader.master_data().collect();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(&results[0], Err(ArchiveError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn quarantine_never_overwrites() {
+        let path = temp_dir("quarantine").join("20.bin");
+        fs::write(&path, b"erste").unwrap();
+        let first = quarantine(&path).unwrap();
+        fs::write(&path, b"zweite").unwrap();
+        let second = quarantine(&path).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read(first).unwrap(), b"erste");
+        assert_eq!(fs::read(second).unwrap(), b"zweite");
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,138 @@
+//! Hilfsfunktionen für die Tests.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+
+use crate::{
+    config::{Config, ElementKind},
+    data::{
+        migrate::{ExportFileV0, LessonV0, SnapshotV0},
+        Lesson, LessonCode, Subject,
+    },
+    privacy::PrivacyPolicy,
+};
+
+/// Zähler für eindeutige Ordnernamen innerhalb eines Testlaufs
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Erstellt einen leeren Ordner im temporären Verzeichnis, der nur von einem Test verwendet wird
+///
+/// # Arguments
+/// * `name` - Name des Tests, wird in den Ordnernamen übernommen
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "school-mining-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Erstellt eine Konfiguration mit den Standardwerten, wie sie ohne optionale Variablen geladen wird
+///
+/// # Arguments
+/// * `path` - Pfad des Speichers
+pub fn test_config(path: &str) -> Config {
+    Config {
+        server: "untis.example".to_string(),
+        school: "schule".to_string(),
+        user: "scraper".to_string(),
+        password: "passwort".to_string(),
+        secret: "geheim".to_string(),
+        path: path.to_string(),
+        state_file_path: None,
+        state_file_check: None,
+        fetch_days_before: 0,
+        fetch_days_ahead: 0,
+        element_types: vec![ElementKind::Class],
+        fetch_workers: 1,
+        request_timeout: 60,
+        max_retries: 3,
+        retry_budget: 50,
+        retry_base_delay: 500,
+        retry_max_delay: 30_000,
+        requests_per_second: 5.0,
+        pseudonym_keys: Vec::new(),
+        privacy_policy: PrivacyPolicy::default(),
+        vault_public_key: None,
+        schedule: Vec::new(),
+        schedule_jitter: 0,
+    }
+}
+
+/// Tag an dem die Unterrichtsstunden der Tests stattfinden, ein Montag
+pub fn test_date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2023, 11, 20).unwrap()
+}
+
+/// Erstellt eine reguläre Mathestunde am [`test_date`]
+///
+/// # Arguments
+/// * `id` - Id der Unterrichtsstunde in Untis
+/// * `class` - Klasse die an der Unterrichtsstunde teilnimmt
+/// * `hour` - Stunde des Beginns, die Unterrichtsstunde dauert 45 Minuten
+pub fn lesson(id: usize, class: &str, hour: u32) -> Lesson {
+    Lesson {
+        id,
+        date: test_date(),
+        start_time: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+        end_time: NaiveTime::from_hms_opt(hour, 45, 0).unwrap(),
+        classes: vec![class.to_string()],
+        teachers: vec!["4f2a".to_string()],
+        rooms: vec!["R101".to_string()],
+        lesson_code: LessonCode::Regular,
+        description: String::new(),
+        subjects: Some(vec![Subject { name: "M".to_string(), long_name: Some("Mathematik".to_string()) }]),
+        sub_text: None,
+        teacher_substitutions: Vec::new(),
+        room_substitutions: Vec::new(),
+    }
+}
+
+/// Zeitpunkt des Snapshots in [`baseline_day_file`]
+pub fn baseline_datetime() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 11, 20, 11, 0, 0).unwrap()
+}
+
+/// Erstellt einen Snapshot im Format des ersten Scrapers (Version 0) mit einer regulären und einer ausgefallenen
+/// Unterrichtsstunde ohne Fach
+pub fn baseline_snapshot() -> SnapshotV0 {
+    SnapshotV0 {
+        datetime: baseline_datetime(),
+        lessons: vec![
+            LessonV0 {
+                classes: vec!["10a".to_string()],
+                teachers: vec!["4f2a".to_string()],
+                rooms: vec!["R101".to_string()],
+                lesson_code: LessonCode::Regular,
+                description: String::new(),
+                topic: "M".to_string(),
+                sub_text: None,
+            },
+            LessonV0 {
+                classes: vec!["10b".to_string()],
+                teachers: Vec::new(),
+                rooms: Vec::new(),
+                lesson_code: LessonCode::Cancelled,
+                description: "Ausfall".to_string(),
+                topic: "None".to_string(),
+                sub_text: Some("Entfall".to_string()),
+            },
+        ],
+    }
+}
+
+/// Erstellt eine Tagesdatei im Format des ersten Scrapers (Version 0), in der das gesamte `ExportFile` am Stück gespeichert ist.
+/// Die Datei enthält den [`baseline_snapshot`].
+pub fn baseline_day_file() -> Vec<u8> {
+    let export_file = ExportFileV0 { date: baseline_datetime(), snapshots: vec![baseline_snapshot()] };
+    rkyv::to_bytes::<_, 1024>(&export_file).unwrap().to_vec()
+}
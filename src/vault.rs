@@ -0,0 +1,195 @@
+//! Tresor für die Re-Identifizierung von Lehrer Pseudonymen.
+//!
+//! Ist `VAULT_PUBLIC_KEY` gesetzt, wird bei jedem Durchlauf für jeden Lehrer aus der Lehrerliste von Untis ein Eintrag mit
+//! Pseudonym und Schlüssel Id an den Tresor angehängt, sofern das Pseudonym dort noch nicht enthalten ist. Der Name des Lehrers
+//! wird als Sealed Box (X25519, XSalsa20-Poly1305) mit dem öffentlichen Schlüssel der Schulleitung verschlüsselt. Der Scraper
+//! kann den Tresor daher nur ergänzen, aber nicht lesen. Nur mit dem geheimen Schlüssel (`VAULT_SECRET_KEY`) kann `reveal`
+//! ein Pseudonym auflösen, jede Abfrage wird vorher im Audit Log protokolliert.
+
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use crypto_box::{aead::OsRng, PublicKey, SecretKey};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pseudonym::{Domain, Pseudonymizer},
+    storage,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Name der Tresor Datei unter `STORAGE_PATH`
+const VAULT_FILE: &str = "vault.jsonl";
+/// Name des Audit Logs unter `STORAGE_PATH`
+const AUDIT_FILE: &str = "vault-audit.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// 'VaultEntry' ist ein Eintrag im Tresor
+pub struct VaultEntry {
+    /// Id des Schlüssels mit dem das Pseudonym gebildet wurde
+    pub key_id: String,
+    /// Pseudonym des Lehrers
+    pub pseudonym: String,
+    /// Zeitpunkt zu dem der Eintrag angelegt wurde
+    pub recorded: DateTime<Utc>,
+    /// Name des Lehrers, als Sealed Box verschlüsselt und hex kodiert
+    sealed: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// 'Identity' ist der verschlüsselte Inhalt eines Eintrags
+pub struct Identity {
+    /// Kürzel des Lehrers in Untis
+    pub name: String,
+    /// Nachname des Lehrers in Untis
+    pub long_name: String,
+}
+
+#[derive(Debug, Serialize)]
+/// 'AuditRecord' ist ein Eintrag im Audit Log
+struct AuditRecord<'a> {
+    /// Zeitpunkt der Abfrage
+    timestamp: DateTime<Utc>,
+    /// Benutzer des Betriebssystems, der die Abfrage ausgeführt hat
+    user: String,
+    /// Abgefragtes Pseudonym
+    pseudonym: &'a str,
+    /// Angegebener Grund der Abfrage
+    reason: &'a str,
+}
+
+/// Gibt den Pfad des Tresors zurück
+pub fn vault_path(storage: &str) -> PathBuf {
+    Path::new(storage).join(VAULT_FILE)
+}
+
+/// Gibt den Pfad des Audit Logs zurück
+pub fn audit_path(storage: &str) -> PathBuf {
+    Path::new(storage).join(AUDIT_FILE)
+}
+
+/// Erzeugt ein neues Schlüsselpaar für den Tresor
+///
+/// # Returns
+/// * `(String, String)` - Öffentlicher und geheimer Schlüssel, hex kodiert
+pub fn generate_keys() -> (String, String) {
+    let secret_key = SecretKey::generate(&mut OsRng);
+    (hex::encode(secret_key.public_key().as_bytes()), hex::encode(secret_key.to_bytes()))
+}
+
+/// Liest alle Einträge des Tresors. Existiert der Tresor noch nicht, wird eine leere Liste zurückgegeben.
+fn read_entries(path: &Path) -> Result<Vec<VaultEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| anyhow::anyhow!("Ungültiger Eintrag in {}: {}", path.display(), e)))
+        .collect()
+}
+
+/// Hängt für alle Lehrer, deren Pseudonym mit diesem Schlüssel noch nicht im Tresor enthalten ist, einen Eintrag an
+///
+/// # Arguments
+/// * `storage` - Pfad an dem die Tagesdateien gespeichert werden
+/// * `public_key` - Öffentlicher Schlüssel des Tresors, hex kodiert
+/// * `pseudonymizer` - Pseudonymizer mit dem die Pseudonyme gebildet werden
+/// * `teachers` - Kürzel und Nachnamen der Lehrer aus Untis
+///
+/// # Returns
+/// * `usize` - Anzahl der neuen Einträge
+pub fn record(storage: &str, public_key: &str, pseudonymizer: &Pseudonymizer, teachers: &[Identity]) -> Result<usize> {
+    let public_key = PublicKey::from(decode_key(public_key, "VAULT_PUBLIC_KEY")?);
+    let path = vault_path(storage);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Die Sperre gilt vom Lesen bis zum Anhängen, damit gleichzeitige Durchläufe kein Pseudonym doppelt anhängen
+    let _lock = storage::lock(&path)?;
+    let known: HashSet<(String, String)> =
+        read_entries(&path)?.into_iter().map(|entry| (entry.key_id, entry.pseudonym)).collect();
+
+    let mut lines = String::new();
+    let mut recorded = HashSet::new();
+    for identity in teachers {
+        let pseudonym = pseudonymizer.pseudonymize(Domain::Teacher, &identity.name);
+        let key = (pseudonymizer.key_id().to_string(), pseudonym);
+        if known.contains(&key) || !recorded.insert(key.clone()) {
+            continue;
+        }
+        let sealed = public_key
+            .seal(&mut OsRng, &serde_json::to_vec(identity)?)
+            .map_err(|_| anyhow::anyhow!("Der Eintrag konnte nicht verschlüsselt werden"))?;
+        let entry = VaultEntry { key_id: key.0, pseudonym: key.1, recorded: Utc::now(), sealed: hex::encode(sealed) };
+        lines.push_str(&serde_json::to_string(&entry)?);
+        lines.push('\n');
+    }
+
+    // Alle neuen Einträge werden mit einem Schreibvorgang angehängt
+    if !lines.is_empty() {
+        OpenOptions::new().create(true).append(true).open(&path)?.write_all(lines.as_bytes())?;
+    }
+    Ok(recorded.len())
+}
+
+/// Löst ein Pseudonym auf. Die Abfrage wird vorher im Audit Log protokolliert, schlägt das fehl, wird nichts aufgelöst.
+///
+/// # Arguments
+/// * `storage` - Pfad an dem die Tagesdateien gespeichert werden
+/// * `secret_key` - Geheimer Schlüssel des Tresors, hex kodiert
+/// * `pseudonym` - Pseudonym das aufgelöst werden soll
+/// * `reason` - Grund der Abfrage, wird im Audit Log gespeichert
+///
+/// # Returns
+/// * `Vec<(VaultEntry, Identity)>` - Alle Einträge mit diesem Pseudonym und die entschlüsselten Namen
+pub fn reveal(storage: &str, secret_key: &str, pseudonym: &str, reason: &str) -> Result<Vec<(VaultEntry, Identity)>> {
+    let secret_key = SecretKey::from(decode_key(secret_key, "VAULT_SECRET_KEY")?);
+    if reason.trim().is_empty() {
+        anyhow::bail!("Für jede Abfrage muss ein Grund angegeben werden");
+    }
+
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME")).unwrap_or_else(|_| "unbekannt".to_string());
+    let audit = AuditRecord { timestamp: Utc::now(), user, pseudonym, reason };
+    let mut line = serde_json::to_string(&audit)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path(storage))
+        .and_then(|mut file| file.write_all(line.as_bytes()).and_then(|_| file.sync_all()))
+        .map_err(|e| anyhow::anyhow!("Die Abfrage konnte nicht im Audit Log protokolliert werden: {}", e))?;
+    info!("Pseudonym {} wurde von {} aufgelöst. Grund: {}", pseudonym, audit.user, reason);
+
+    read_entries(&vault_path(storage))?
+        .into_iter()
+        .filter(|entry| entry.pseudonym == pseudonym)
+        .map(|entry| {
+            let plaintext = secret_key
+                .unseal(&hex::decode(&entry.sealed)?)
+                .map_err(|_| anyhow::anyhow!("Der Eintrag für {} konnte mit dem Schlüssel nicht entschlüsselt werden", entry.pseudonym))?;
+            let identity = serde_json::from_slice(&plaintext)?;
+            Ok((entry, identity))
+        })
+        .collect()
+}
+
+/// Dekodiert einen hex kodierten Schlüssel mit 32 Byte
+///
+/// # Arguments
+/// * `key` - Hex kodierter Schlüssel
+/// * `name` - Name der Variable für die Fehlermeldung
+fn decode_key(key: &str, name: &str) -> Result<[u8; 32]> {
+    hex::decode(key.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("Variable {} muss ein hex kodierter Schlüssel mit 32 Byte sein", name))
+}